@@ -1,125 +1,486 @@
+use std::io::Write;
+use std::path::Path;
+use std::sync::RwLock;
+use std::time::Duration;
+
 use super::api_error::*;
+use super::history::*;
 use super::model::*;
 use super::util::*;
 
-const DYSON_API_URL: &'static str = "https://appapi.cp.dyson.com";
+pub const DYSON_API_URL: &str = "https://appapi.cp.dyson.com";
+pub const DYSON_API_URL_CHINA: &str = "https://appapi.cp.dyson.cn";
+
+const ACCOUNT_STATUS_ACTIVE: &str = "ACTIVE";
+const AUTHENTICATION_METHOD_OTP: &str = "EMAIL_PWD_2FA";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Region {
+    Global,
+    China
+}
+
+impl Region {
+    pub fn api_url(self) -> &'static str {
+        match self {
+            Region::Global => DYSON_API_URL,
+            Region::China => DYSON_API_URL_CHINA
+        }
+    }
+}
+
+/// Base URL and HTTP client shared by every request of a client.
+#[derive(Clone, Debug)]
+struct ApiEndpoint {
+    api_url: String,
+    http_client: reqwest::Client
+}
+
+impl Default for ApiEndpoint {
+    fn default() -> Self {
+        ApiEndpoint {
+            api_url: String::from(DYSON_API_URL),
+            http_client: reqwest::Client::new()
+        }
+    }
+}
+
+impl ApiEndpoint {
+    async fn post_json<B, T>(&self, url: &str, body: &B) -> Result<T, DysonError>
+        where B: serde::Serialize,
+              for<'de> T: serde::Deserialize<'de>
+    {
+        let response = self.http_client.post(url)
+            .json(body)
+            .send()
+            .await?;
+
+        parse_json(read_response(response).await?)
+    }
+}
+
+#[derive(Debug)]
+pub struct DysonClientBuilder {
+    api_url: Option<String>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    user_agent: Option<String>,
+    proxy: Option<reqwest::Proxy>,
+    http_client: Option<reqwest::Client>
+}
+
+impl Default for DysonClientBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DysonClientBuilder {
+    pub fn new() -> Self {
+        DysonClientBuilder {
+            api_url: None,
+            timeout: None,
+            connect_timeout: None,
+            user_agent: None,
+            proxy: None,
+            http_client: None
+        }
+    }
+
+    pub fn region(mut self, region: Region) -> Self {
+        self.api_url = Some(String::from(region.api_url()));
+        self
+    }
+
+    /// Overrides the cloud endpoint, e.g. with a local mock server.
+    pub fn api_url(mut self, api_url: &str) -> Self {
+        self.api_url = Some(String::from(api_url.trim_end_matches('/')));
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = Some(connect_timeout);
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = Some(String::from(user_agent));
+        self
+    }
+
+    pub fn proxy(mut self, proxy: reqwest::Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Shares an existing client and its connection pool.
+    /// Timeouts, user agent and proxy are then taken from that client and ignored here.
+    pub fn http_client(mut self, http_client: reqwest::Client) -> Self {
+        self.http_client = Some(http_client);
+        self
+    }
+
+    /// Logs in with the legacy password endpoint only.
+    pub async fn login(self, user_credentials: UserCredentials) -> Result<DysonClient, DysonError> {
+        DysonClient::login_with(self.endpoint()?, user_credentials).await
+    }
+
+    /// Starts a login that uses the emailed one-time code when the account requires it,
+    /// and falls back to the legacy password login otherwise.
+    pub async fn begin_login(self, user_credentials: UserCredentials) -> Result<LoginFlow, DysonError> {
+        DysonClient::begin_login_with(self.endpoint()?, user_credentials).await
+    }
+
+    /// Restores a client from an exported session without contacting the cloud.
+    ///
+    /// Uses the endpoint saved with the session unless `region` or `api_url` was set.
+    pub fn resume(mut self, session: DysonSession) -> Result<DysonClient, DysonError> {
+        if self.api_url.is_none() {
+            self.api_url = session.api_url.clone();
+        }
+
+        Ok(DysonClient::resume_with(self.endpoint()?, session))
+    }
+
+    pub fn resume_from_file<P: AsRef<Path>>(self, path: P) -> Result<DysonClient, DysonError> {
+        let session = read_session(path)?;
+
+        self.resume(session)
+    }
+
+    fn endpoint(self) -> Result<ApiEndpoint, DysonError> {
+        let http_client = match self.http_client {
+            Some(http_client) => http_client,
+            None => {
+                let mut builder = reqwest::Client::builder();
+
+                if let Some(timeout) = self.timeout {
+                    builder = builder.timeout(timeout);
+                }
+                if let Some(connect_timeout) = self.connect_timeout {
+                    builder = builder.connect_timeout(connect_timeout);
+                }
+                if let Some(user_agent) = self.user_agent {
+                    builder = builder.user_agent(user_agent);
+                }
+                if let Some(proxy) = self.proxy {
+                    builder = builder.proxy(proxy);
+                }
+
+                builder.build()?
+            }
+        };
+
+        Ok(ApiEndpoint {
+            api_url: self.api_url.unwrap_or_else(|| String::from(DYSON_API_URL)),
+            http_client
+        })
+    }
+}
 
 pub struct DysonClient {
+    pub user_credentials: Option<UserCredentials>,
+    pub country_code: String,
+    pub device_manifest: Vec<DeviceManifest>,
+    authentication: RwLock<Authentication>,
+    authenticated_at: RwLock<chrono::DateTime<chrono::Utc>>,
+    endpoint: ApiEndpoint
+}
+
+/// Outcome of the first login step.
+pub enum LoginFlow {
+    /// The account still uses the legacy password login, which has already completed.
+    Complete(DysonClient),
+    /// A one-time code has been emailed and must be passed to `LoginChallenge::verify_otp`.
+    OtpRequired(LoginChallenge)
+}
+
+pub struct LoginChallenge {
     pub user_credentials: UserCredentials,
-    pub account_credentials: AccountCredentials,
-    pub device_manifest: Vec<DeviceManifest>
+    pub challenge_id: String,
+    endpoint: ApiEndpoint
+}
+
+impl LoginChallenge {
+    pub async fn verify_otp(self, otp_code: &str) -> Result<DysonClient, DysonError> {
+        let url = format!("{}/v3/userregistration/email/verify?country={}",
+                          self.endpoint.api_url,
+                          self.user_credentials.country_code);
+
+        let body = serde_json::json!({
+            "email": self.user_credentials.email,
+            "password": self.user_credentials.password,
+            "challengeId": self.challenge_id,
+            "otpCode": otp_code
+        });
+
+        let token_credentials: TokenCredentials = self.endpoint.post_json(&url, &body).await?;
+
+        let mut dyson_client = DysonClient::new(self.endpoint,
+                                                &self.user_credentials.country_code,
+                                                Authentication::Token(token_credentials));
+        dyson_client.user_credentials = Some(self.user_credentials);
+
+        dyson_client.get_devices_manifest().await?;
+        Ok(dyson_client)
+    }
 }
 
 impl DysonClient {
-    pub async fn login(user_credentials: UserCredentials) -> Result<Self, Box<dyn std::error::Error>> {
-        let mut dyson_client = DysonClient{
-            user_credentials,
-            account_credentials: Default::default(),
-            device_manifest: vec![]
-        };
+    pub fn builder() -> DysonClientBuilder {
+        DysonClientBuilder::new()
+    }
+
+    /// Logs in with the legacy password endpoint only.
+    pub async fn login(user_credentials: UserCredentials) -> Result<Self, DysonError> {
+        Self::login_with(ApiEndpoint::default(), user_credentials).await
+    }
+
+    /// Starts a login that uses the emailed one-time code when the account requires it,
+    /// and falls back to the legacy password login otherwise.
+    pub async fn begin_login(user_credentials: UserCredentials) -> Result<LoginFlow, DysonError> {
+        Self::begin_login_with(ApiEndpoint::default(), user_credentials).await
+    }
+
+    /// Restores a client from an exported session without contacting the cloud.
+    ///
+    /// Sessions don't store `user_credentials`, so once the cloud stops accepting them requests
+    /// fail with `DysonError::AuthenticationRejected` and the caller has to log in again.
+    /// Setting `user_credentials` on the result lets account sessions renew themselves through
+    /// the legacy password login; token sessions always need a new one-time code.
+    pub fn resume(session: DysonSession) -> Self {
+        let mut endpoint = ApiEndpoint::default();
+        if let Some(api_url) = &session.api_url {
+            endpoint.api_url = api_url.clone();
+        }
+
+        Self::resume_with(endpoint, session)
+    }
+
+    pub fn resume_from_file<P: AsRef<Path>>(path: P) -> Result<Self, DysonError> {
+        Ok(Self::resume(read_session(path)?))
+    }
+
+    pub fn export_session(&self) -> DysonSession {
+        DysonSession {
+            authentication: self.authentication(),
+            country_code: self.country_code.clone(),
+            api_url: Some(self.endpoint.api_url.clone()),
+            device_manifest: self.device_manifest.clone(),
+            created_at: *self.authenticated_at.read().unwrap()
+        }
+    }
+
+    pub fn save_session<P: AsRef<Path>>(&self, path: P) -> Result<(), DysonError> {
+        let content = serde_json::to_string_pretty(&self.export_session())
+            .map_err(DysonError::Serialize)?;
+
+        write_private_file(path, content.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn authentication(&self) -> Authentication {
+        self.authentication.read().unwrap().clone()
+    }
+
+    pub fn api_url(&self) -> &str {
+        &self.endpoint.api_url
+    }
+
+    fn new(endpoint: ApiEndpoint, country_code: &str, authentication: Authentication) -> Self {
+        DysonClient{
+            user_credentials: None,
+            country_code: String::from(country_code),
+            device_manifest: vec![],
+            authentication: RwLock::new(authentication),
+            authenticated_at: RwLock::new(chrono::Utc::now()),
+            endpoint
+        }
+    }
+
+    async fn login_with(endpoint: ApiEndpoint, user_credentials: UserCredentials) -> Result<Self, DysonError> {
+        let mut dyson_client = DysonClient::new(endpoint,
+                                                &user_credentials.country_code,
+                                                Authentication::Account(Default::default()));
+        dyson_client.user_credentials = Some(user_credentials);
 
         dyson_client.get_account_credentials().await?;
         dyson_client.get_devices_manifest().await?;
         Ok(dyson_client)
     }
 
-    async fn get_account_credentials(&mut self) -> Result<AccountCredentials, Box<dyn std::error::Error>> {
-        let url = format!("{}/v1/userregistration/authenticate?country={}",
-                          DYSON_API_URL,
-                          &self.user_credentials.country_code);
+    async fn begin_login_with(endpoint: ApiEndpoint, user_credentials: UserCredentials) -> Result<LoginFlow, DysonError> {
+        let body = serde_json::json!({ "email": user_credentials.email });
 
-        let client = reqwest::Client::new();
-        let response = client.post(&url)
-            .json(&self.user_credentials)
-            .send()
-            .await?;
+        let url = format!("{}/v3/userregistration/email/userstatus?country={}",
+                          endpoint.api_url,
+                          user_credentials.country_code);
+        let user_status: UserStatus = endpoint.post_json(&url, &body).await?;
+
+        if user_status.account_status != ACCOUNT_STATUS_ACTIVE {
+            return Err(DysonError::AccountInactive(user_status.account_status));
+        }
+
+        if user_status.authentication_method != AUTHENTICATION_METHOD_OTP {
+            return Ok(LoginFlow::Complete(Self::login_with(endpoint, user_credentials).await?));
+        }
+
+        let url = format!("{}/v3/userregistration/email/auth?country={}&culture=en-{}",
+                          endpoint.api_url,
+                          user_credentials.country_code,
+                          user_credentials.country_code);
+        let challenge: OtpChallenge = endpoint.post_json(&url, &body).await?;
+
+        Ok(LoginFlow::OtpRequired(LoginChallenge {
+            user_credentials,
+            challenge_id: challenge.challenge_id,
+            endpoint
+        }))
+    }
+
+    fn resume_with(endpoint: ApiEndpoint, session: DysonSession) -> Self {
+        let mut dyson_client = DysonClient::new(endpoint, &session.country_code, session.authentication);
+        dyson_client.device_manifest = session.device_manifest;
+        dyson_client.authenticated_at = RwLock::new(session.created_at);
+
+        dyson_client
+    }
 
-        let account_credentials: AccountCredentials = response.json().await?;
-        self.account_credentials = account_credentials.clone();
+    async fn get_account_credentials(&self) -> Result<AccountCredentials, DysonError> {
+        let user_credentials = match &self.user_credentials {
+            Some(user_credentials) => user_credentials,
+            None => return Err(DysonError::MissingCredentials)
+        };
+
+        let url = format!("{}/v1/userregistration/authenticate?country={}",
+                          self.endpoint.api_url,
+                          user_credentials.country_code);
+
+        let account_credentials: AccountCredentials = self.endpoint.post_json(&url, user_credentials).await?;
+        *self.authentication.write().unwrap() = Authentication::Account(account_credentials.clone());
+        *self.authenticated_at.write().unwrap() = chrono::Utc::now();
         Ok(account_credentials)
     }
 
-    async fn get_devices_manifest(&mut self) -> Result<Vec<DeviceManifest>, Box<dyn std::error::Error>> {
-        let url = format!("{}/v2/provisioningservice/manifest", DYSON_API_URL);
+    async fn get_devices_manifest(&mut self) -> Result<Vec<DeviceManifest>, DysonError> {
+        let url = format!("{}/v2/provisioningservice/manifest", self.endpoint.api_url);
 
         let device_manifest: Vec<DeviceManifest> = self.request_dyson_api_json(&url).await?;
         self.device_manifest = device_manifest.clone();
         Ok(device_manifest)
     }
 
-    pub async fn get_device_environment_data(&self, device: &str) -> Result<EnvironmentData, Box<dyn std::error::Error>> {
+    pub async fn get_device_environment_data(&self, device: &str) -> Result<EnvironmentData, DysonError> {
         let url = format!("{}/v1/environment/devices/{}/data?language={}",
-                            DYSON_API_URL,
+                            self.endpoint.api_url,
                             device,
-                            self.user_credentials.country_code);
+                            self.country_code);
 
         Ok(self.request_dyson_api_json(&url).await?)
     }
 
-    pub async fn get_device_environment_data_help(&self, device: &str) -> Result<EnvironmentDataHelp, Box<dyn std::error::Error>> {
+    pub async fn get_device_environment_data_help(&self, device: &str) -> Result<EnvironmentDataHelp, DysonError> {
         let url = format!("{}/v1/environment/devices/{}/help?language={}",
-                          DYSON_API_URL,
+                          self.endpoint.api_url,
                           device,
-                          self.user_credentials.country_code);
+                          self.country_code);
 
         Ok(self.request_dyson_api_json(&url).await?)
     }
 
-    pub async fn get_device_environment_data_daily_legacy(&self, device: &str) -> Result<Vec<EnvironmentDataDaily>, Box<dyn std::error::Error>> {
+    pub async fn get_device_environment_data_daily_legacy(&self, device: &str) -> Result<Vec<EnvironmentDataDaily>, DysonError> {
         let url = format!("{}/v1/messageprocessor/devices/{}/environmentdailyhistory",
-                          DYSON_API_URL,
+                          self.endpoint.api_url,
                           device);
 
         Ok(self.request_dyson_api_json(&url).await?)
     }
 
-    pub async fn get_device_environment_data_weekly_legacy(&self, device: &str) -> Result<Vec<EnvironmentDataWeekly>, Box<dyn std::error::Error>> {
+    pub async fn get_device_environment_data_weekly_legacy(&self, device: &str) -> Result<Vec<EnvironmentDataWeekly>, DysonError> {
         let url = format!("{}/v1/messageprocessor/devices/{}/environmentweeklyhistory",
-                          DYSON_API_URL,
+                          self.endpoint.api_url,
                           device);
 
         Ok(self.request_dyson_api_json(&url).await?)
     }
 
-    pub async fn get_device_environment_data_weekly(&self, device: &str) -> Result<String, Box<dyn std::error::Error>> {
+    pub async fn get_device_environment_data_weekly(&self, device: &str) -> Result<EnvironmentDataHistory, DysonError> {
         let url = format!("{}/v1/messageprocessor/devices/{}/environmentdata/weekly",
-                          DYSON_API_URL,
+                          self.endpoint.api_url,
                           device);
 
-        Ok(self.request_dyson_api_text(&url).await?)
+        Ok(self.request_dyson_api_json(&url).await?)
     }
 
-    pub async fn get_device_environment_data_daily(&self, device: &str) -> Result<String, Box<dyn std::error::Error>> {
+    pub async fn get_device_environment_data_daily(&self, device: &str) -> Result<EnvironmentDataHistory, DysonError> {
         let url = format!("{}/v1/messageprocessor/devices/{}/environmentdata/daily",
-                          DYSON_API_URL,
+                          self.endpoint.api_url,
                           device);
 
-        Ok(self.request_dyson_api_text(&url).await?)
+        Ok(self.request_dyson_api_json(&url).await?)
     }
 
-    pub async fn request_dyson_api_json<'a, T>(&self, url: &str) -> Result<T, Box<dyn std::error::Error>>
+    /// Reads the history from the newer endpoint and falls back to the legacy one
+    /// when the account doesn't support it yet.
+    pub async fn get_device_environment_history(&self, device: &str, resolution: HistoryResolution) -> Result<EnvironmentHistory, DysonError> {
+        let history = match resolution {
+            HistoryResolution::Daily => self.get_device_environment_data_daily(device).await,
+            HistoryResolution::Weekly => self.get_device_environment_data_weekly(device).await
+        };
+
+        match history {
+            Ok(history) => Ok(EnvironmentHistory::from_history(resolution, &history)),
+            Err(DysonError::Status { status, .. }) if status == reqwest::StatusCode::NOT_FOUND => match resolution {
+                HistoryResolution::Daily =>
+                    Ok(EnvironmentHistory::from_legacy_daily(&self.get_device_environment_data_daily_legacy(device).await?)),
+                HistoryResolution::Weekly =>
+                    Ok(EnvironmentHistory::from_legacy_weekly(&self.get_device_environment_data_weekly_legacy(device).await?))
+            },
+            Err(err) => Err(err)
+        }
+    }
+
+    pub async fn request_dyson_api_json<'a, T>(&self, url: &str) -> Result<T, DysonError>
         where for<'de> T: serde::Deserialize<'de> + 'a
     {
-        let client = reqwest::Client::new();
-        let response = client.get(url)
-            .basic_auth(&self.account_credentials.account, Some(&self.account_credentials.password))
-            .send()
-            .await?;
+        parse_json(self.request_dyson_api_text(url).await?)
+    }
 
-        Ok(response.json().await?)
+    /// Logs in again with the legacy password endpoint when the cloud rejects the current
+    /// account credentials, as long as `user_credentials` are available. Any other rejection,
+    /// including an expired token, is returned as `DysonError::AuthenticationRejected`.
+    pub async fn request_dyson_api_text(&self, url: &str) -> Result<String, DysonError> {
+        match self.request_authorized(url).await {
+            Err(DysonError::AuthenticationRejected { .. }) if self.can_reauthenticate() => {
+                self.get_account_credentials().await?;
+                self.request_authorized(url).await
+            },
+            result => result
+        }
     }
 
-    pub async fn request_dyson_api_text(&self, url: &str) -> Result<String, Box<dyn std::error::Error>> {
-        let client = reqwest::Client::new();
-        let response = client.get(url)
-            .basic_auth(&self.account_credentials.account, Some(&self.account_credentials.password))
-            .send()
-            .await?;
+    fn can_reauthenticate(&self) -> bool {
+        self.user_credentials.is_some() && matches!(self.authentication(), Authentication::Account(_))
+    }
 
-        Ok(response.text().await?)
+    async fn request_authorized(&self, url: &str) -> Result<String, DysonError> {
+        let client = &self.endpoint.http_client;
+        let request = match self.authentication() {
+            Authentication::Account(account_credentials) =>
+                client.get(url).basic_auth(&account_credentials.account, Some(&account_credentials.password)),
+            Authentication::Token(token_credentials) =>
+                client.get(url).bearer_auth(&token_credentials.token)
+        };
+
+        read_response(request.send().await?).await
     }
 
-    fn decrypt_local_credentials(local_credentials: &str) -> Result<DecryptedLocalCredentials, DecryptCredentialsError> {
+    pub fn decrypt_local_credentials(local_credentials: &str) -> Result<DecryptedLocalCredentials, DecryptCredentialsError> {
         let key = (0..0x20).map(|x| x + 1)
             .collect::<Vec<u8>>();
 
@@ -134,9 +495,323 @@ impl DysonClient {
     }
 }
 
+/// Sessions hold reusable credentials, so only the owner may read them.
+fn write_private_file<P: AsRef<Path>>(path: P, content: &[u8]) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)?;
+
+    // the mode only applies to new files
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+
+    file.write_all(content)
+}
+
+fn read_session<P: AsRef<Path>>(path: P) -> Result<DysonSession, DysonError> {
+    parse_json(std::fs::read_to_string(path)?)
+}
+
+async fn read_response(response: reqwest::Response) -> Result<String, DysonError> {
+    let status = response.status();
+    let body = response.text().await?;
+
+    match status {
+        reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN =>
+            Err(DysonError::AuthenticationRejected { status, body }),
+        status if !status.is_success() =>
+            Err(DysonError::Status { status, body }),
+        _ => Ok(body)
+    }
+}
+
+fn parse_json<T>(payload: String) -> Result<T, DysonError>
+    where for<'de> T: serde::Deserialize<'de>
+{
+    serde_json::from_str(&payload)
+        .map_err(|source| DysonError::Json { source, payload })
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use mockito::{mock, Matcher};
+
+    fn user_credentials() -> UserCredentials {
+        UserCredentials {
+            email: String::from("user@example.com"),
+            password: String::from("secret"),
+            country_code: String::from("GB")
+        }
+    }
+
+    fn mock_server() -> DysonClientBuilder {
+        DysonClient::builder().api_url(&mockito::server_url())
+    }
+
+    fn mock_user_status(authentication_method: &str) -> mockito::Mock {
+        mock("POST", "/v3/userregistration/email/userstatus")
+            .match_query(Matcher::UrlEncoded(String::from("country"), String::from("GB")))
+            .match_body(Matcher::Json(serde_json::json!({ "email": "user@example.com" })))
+            .with_body(format!(r#"{{"accountStatus":"ACTIVE","authenticationMethod":"{}"}}"#, authentication_method))
+            .create()
+    }
+
+    #[tokio::test]
+    async fn it_logs_in_with_otp_code() {
+        let _user_status = mock_user_status("EMAIL_PWD_2FA");
+        let _auth = mock("POST", "/v3/userregistration/email/auth")
+            .match_query(Matcher::Any)
+            .with_body(r#"{"challengeId":"5b1c2d3e"}"#)
+            .create();
+        let _verify = mock("POST", "/v3/userregistration/email/verify")
+            .match_query(Matcher::UrlEncoded(String::from("country"), String::from("GB")))
+            .match_body(Matcher::Json(serde_json::json!({
+                "email": "user@example.com",
+                "password": "secret",
+                "challengeId": "5b1c2d3e",
+                "otpCode": "123456"
+            })))
+            .with_body(r#"{"account":"7f3e","token":"token-value","tokenType":"Bearer"}"#)
+            .create();
+        let manifest = mock("GET", "/v2/provisioningservice/manifest")
+            .match_header("authorization", "Bearer token-value")
+            .with_body("[]")
+            .create();
+
+        let challenge = match mock_server().begin_login(user_credentials()).await.unwrap() {
+            LoginFlow::OtpRequired(challenge) => challenge,
+            LoginFlow::Complete(_) => panic!("expected an otp challenge")
+        };
+        assert_eq!(challenge.challenge_id, "5b1c2d3e");
+
+        let dyson_client = challenge.verify_otp("123456").await.unwrap();
+
+        manifest.assert();
+        match dyson_client.authentication() {
+            Authentication::Token(token_credentials) => assert_eq!(token_credentials.token, "token-value"),
+            other => panic!("unexpected authentication {:?}", other)
+        }
+    }
+
+    #[tokio::test]
+    async fn it_falls_back_to_legacy_login() {
+        let _user_status = mock_user_status("EMAIL_PWD");
+        let _authenticate = mock("POST", "/v1/userregistration/authenticate")
+            .match_query(Matcher::UrlEncoded(String::from("country"), String::from("GB")))
+            .with_body(r#"{"Account":"7f3e","Password":"account-password"}"#)
+            .create();
+        let manifest = mock("GET", "/v2/provisioningservice/manifest")
+            .match_header("authorization", format!("Basic {}", base64::encode("7f3e:account-password")).as_str())
+            .with_body("[]")
+            .create();
+
+        let flow = mock_server().begin_login(user_credentials()).await.unwrap();
+
+        manifest.assert();
+        assert!(matches!(flow, LoginFlow::Complete(_)));
+    }
+
+    #[tokio::test]
+    async fn it_rejects_wrong_otp_code() {
+        let _verify = mock("POST", "/v3/userregistration/email/verify")
+            .match_query(Matcher::Any)
+            .with_status(401)
+            .with_body(r#"{"Message":"Invalid otp"}"#)
+            .create();
+
+        let challenge = LoginChallenge {
+            user_credentials: user_credentials(),
+            challenge_id: String::from("5b1c2d3e"),
+            endpoint: mock_server().endpoint().unwrap()
+        };
+
+        let actual = challenge.verify_otp("000000").await;
+
+        assert!(matches!(actual, Err(DysonError::AuthenticationRejected { .. })));
+    }
+
+    fn account_session(password: &str) -> DysonSession {
+        DysonSession {
+            authentication: Authentication::Account(AccountCredentials {
+                account: String::from("7f3e"),
+                password: String::from(password)
+            }),
+            country_code: String::from("GB"),
+            api_url: None,
+            device_manifest: vec![],
+            created_at: chrono::Utc::now()
+        }
+    }
+
+    #[tokio::test]
+    async fn it_reauthenticates_when_session_expired() {
+        let _expired = mock("GET", "/v2/provisioningservice/manifest")
+            .match_header("authorization", format!("Basic {}", base64::encode("7f3e:expired")).as_str())
+            .with_status(401)
+            .create();
+        let authenticate = mock("POST", "/v1/userregistration/authenticate")
+            .match_query(Matcher::Any)
+            .with_body(r#"{"Account":"7f3e","Password":"renewed"}"#)
+            .create();
+        let manifest = mock("GET", "/v2/provisioningservice/manifest")
+            .match_header("authorization", format!("Basic {}", base64::encode("7f3e:renewed")).as_str())
+            .with_body("[]")
+            .create();
+
+        let mut dyson_client = mock_server().resume(account_session("expired")).unwrap();
+        dyson_client.user_credentials = Some(user_credentials());
+
+        dyson_client.get_devices_manifest().await.unwrap();
+
+        authenticate.assert();
+        manifest.assert();
+        match dyson_client.authentication() {
+            Authentication::Account(account_credentials) => assert_eq!(account_credentials.password, "renewed"),
+            other => panic!("unexpected authentication {:?}", other)
+        }
+    }
+
+    #[tokio::test]
+    async fn it_reports_expired_session_without_credentials() {
+        let _expired = mock("GET", "/v2/provisioningservice/manifest")
+            .match_header("authorization", format!("Basic {}", base64::encode("7f3e:stale")).as_str())
+            .with_status(401)
+            .create();
+
+        let mut dyson_client = mock_server().resume(account_session("stale")).unwrap();
+
+        let actual = dyson_client.get_devices_manifest().await;
+
+        assert!(matches!(actual, Err(DysonError::AuthenticationRejected { .. })));
+    }
+
+    #[tokio::test]
+    async fn it_reports_expired_token_without_logging_in() {
+        let _expired = mock("GET", "/v2/provisioningservice/manifest")
+            .match_header("authorization", "Bearer expired-token")
+            .with_status(401)
+            .create();
+
+        let session = DysonSession {
+            authentication: Authentication::Token(TokenCredentials {
+                account: String::from("7f3e"),
+                token: String::from("expired-token"),
+                token_type: String::from("Bearer")
+            }),
+            ..account_session("unused")
+        };
+        let mut dyson_client = mock_server().resume(session).unwrap();
+        dyson_client.user_credentials = Some(user_credentials());
+
+        let actual = dyson_client.get_devices_manifest().await;
+
+        assert!(matches!(actual, Err(DysonError::AuthenticationRejected { .. })));
+    }
+
+    #[tokio::test]
+    async fn it_uses_configured_http_client() {
+        let manifest = mock("GET", "/v2/provisioningservice/manifest")
+            .match_header("user-agent", "android client")
+            .with_body("[]")
+            .create();
+
+        let mut dyson_client = mock_server()
+            .user_agent("android client")
+            .timeout(Duration::from_secs(5))
+            .resume(account_session("account-password"))
+            .unwrap();
+
+        dyson_client.get_devices_manifest().await.unwrap();
+
+        manifest.assert();
+    }
+
+    #[test]
+    fn it_selects_region_endpoint() {
+        let dyson_client = DysonClient::builder()
+            .region(Region::China)
+            .resume(account_session("account-password"))
+            .unwrap();
+
+        assert_eq!(dyson_client.api_url(), DYSON_API_URL_CHINA);
+    }
+
+    #[test]
+    fn it_resumes_against_the_session_endpoint() {
+        let session = DysonSession {
+            api_url: Some(String::from(DYSON_API_URL_CHINA)),
+            ..account_session("account-password")
+        };
+
+        let dyson_client = DysonClient::resume(session.clone());
+        assert_eq!(dyson_client.api_url(), DYSON_API_URL_CHINA);
+        assert_eq!(dyson_client.export_session().api_url.as_deref(), Some(DYSON_API_URL_CHINA));
+
+        let dyson_client = DysonClient::builder().resume(session.clone()).unwrap();
+        assert_eq!(dyson_client.api_url(), DYSON_API_URL_CHINA);
+
+        let dyson_client = mock_server().resume(session).unwrap();
+        assert_eq!(dyson_client.api_url(), mockito::server_url());
+
+        assert_eq!(DysonClient::resume(account_session("account-password")).api_url(), DYSON_API_URL);
+    }
+
+    #[tokio::test]
+    async fn it_falls_back_to_legacy_history() {
+        let _history = mock("GET", "/v1/messageprocessor/devices/ABC-DE-FGH1234A/environmentdata/daily")
+            .with_status(404)
+            .create();
+        let legacy = mock("GET", "/v1/messageprocessor/devices/ABC-DE-FGH1234A/environmentdailyhistory")
+            .with_body(r#"[{"Aqi":[1.0],"AverageAqi":1.0,"AverageHumidity":45,"Date":"2020-05-19","Humidity":[45],
+                           "MaxTemperature":23,"MinTemperature":23,"Temperature":[23],"TotalUsage":60,"Usage":[60]}]"#)
+            .create();
+
+        let dyson_client = mock_server().resume(account_session("account-password")).unwrap();
+
+        let actual = dyson_client.get_device_environment_history("ABC-DE-FGH1234A", HistoryResolution::Daily).await.unwrap();
+
+        legacy.assert();
+        assert_eq!(actual.resolution, HistoryResolution::Daily);
+        assert_eq!(actual.periods[0].humidity, vec![Some(45.0)]);
+    }
+
+    #[test]
+    fn it_saves_and_resumes_session() {
+        let path = std::env::temp_dir().join(format!("dyson-session-{}.json", std::process::id()));
+        let session = DysonSession {
+            created_at: chrono::Utc::now() - chrono::Duration::days(3),
+            ..account_session("account-password")
+        };
+        let dyson_client = DysonClient::resume(session.clone());
+
+        dyson_client.save_session(&path).unwrap();
+        let actual = DysonClient::resume_from_file(&path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(actual.country_code, "GB");
+        assert_eq!(actual.export_session().created_at, session.created_at);
+        assert!(actual.user_credentials.is_none());
+        match actual.authentication() {
+            Authentication::Account(account_credentials) => assert_eq!(account_credentials.password, "account-password"),
+            other => panic!("unexpected authentication {:?}", other)
+        }
+    }
 
     #[test]
     fn it_decrypt_local_credentials() {
@@ -150,4 +825,49 @@ mod test {
 
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn it_derives_local_mqtt_credentials() {
+        let device_manifest: DeviceManifest = serde_json::from_str(r#"
+            {
+                "Serial": "ABC-DE-FGH1234A",
+                "Name": "Living room",
+                "Version": "21.03.08",
+                "LocalCredentials": "FpW3nD8izChaGMi60A4impGpdTtZKRc6JsMZfx1u5i2NWanM2aF4t34o9l0ScFciO1CC7EYPfjNjr/hszayQJoWB7tOArk8Y0X4gQjYaMhz+mbm+rIl+2nQimb6kxCfzLM92U7EI4Jz7hyRqkfA3coWF0zcixptQ+n5/YZeCxmuBT+CP7gfCzfe38x5VLPhb",
+                "AutoUpdate": true,
+                "NewVersionAvailable": false,
+                "ProductType": "455",
+                "ConnectionType": "wss"
+            }"#).unwrap();
+
+        let actual = device_manifest.local_mqtt_credentials().unwrap();
+
+        assert_eq!(actual.username, "ABC-DE-FGH1234A");
+        assert_eq!(actual.password, sha512_base64("abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789+/=abcdefghijklmnopqrstuvw"));
+
+        let device_manifest = DeviceManifest {
+            local_credentials: String::new(),
+            connected_configuration: Some(ConnectedConfiguration {
+                mqtt: Some(MqttConfiguration {
+                    local_broker_credentials: device_manifest.local_credentials.clone(),
+                    root_topic_level: Some(String::from("455"))
+                })
+            }),
+            ..device_manifest
+        };
+
+        assert_eq!(device_manifest.local_mqtt_credentials().unwrap(), actual);
+    }
+
+    #[test]
+    fn it_keeps_payload_of_unexpected_json() {
+        let payload = String::from(r#"{"Message":"Unable to authenticate user."}"#);
+
+        let actual = parse_json::<AccountCredentials>(payload.clone()).unwrap_err();
+
+        match actual {
+            DysonError::Json { payload: actual_payload, .. } => assert_eq!(actual_payload, payload),
+            other => panic!("unexpected error {:?}", other)
+        }
+    }
 }
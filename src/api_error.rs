@@ -1,3 +1,5 @@
+use super::product::ProductType;
+
 #[derive(Debug)]
 pub enum DecryptCredentialsError {
     Crypto(crypto::symmetriccipher::SymmetricCipherError),
@@ -29,3 +31,175 @@ impl From<base64::DecodeError> for DecryptCredentialsError {
         DecryptCredentialsError::Base64Decoding(err)
     }
 }
+
+impl std::fmt::Display for DecryptCredentialsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecryptCredentialsError::Crypto(err) => write!(f, "failed to decrypt local credentials: {:?}", err),
+            DecryptCredentialsError::Base64Decoding(err) => write!(f, "local credentials are not valid base64: {}", err),
+            DecryptCredentialsError::UtfEncoding(err) => write!(f, "decrypted local credentials are not valid utf-8: {}", err),
+            DecryptCredentialsError::Json(err) => write!(f, "decrypted local credentials are not valid json: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for DecryptCredentialsError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandError {
+    Empty,
+    HeatTargetOutOfRange(f32),
+    ConflictingSettings(&'static str),
+    OscillationAnglesOutOfRange(u16, u16),
+    HumidityTargetOutOfRange(u8),
+    SleepTimerOutOfRange(u16),
+    HysteresisOutOfRange(f32),
+    Unsupported {
+        product_type: ProductType,
+        setting: &'static str
+    }
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandError::Empty => write!(f, "command does not change any setting"),
+            CommandError::HeatTargetOutOfRange(kelvin) => write!(f, "heat target {}K is outside the device range", kelvin),
+            CommandError::ConflictingSettings(reason) => write!(f, "conflicting settings: {}", reason),
+            CommandError::OscillationAnglesOutOfRange(low, high) => write!(f, "oscillation angles {}-{} are outside the device range", low, high),
+            CommandError::HumidityTargetOutOfRange(percent) => write!(f, "humidity target {}% is outside the device range", percent),
+            CommandError::SleepTimerOutOfRange(minutes) => write!(f, "sleep timer of {} minutes is outside the device range", minutes),
+            CommandError::HysteresisOutOfRange(kelvin) => write!(f, "thermostat hysteresis {}K must not be negative", kelvin),
+            CommandError::Unsupported { product_type, setting } => write!(f, "{} does not support {}", product_type, setting),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+#[derive(Debug)]
+pub enum DysonError {
+    Http(reqwest::Error),
+    Status {
+        status: reqwest::StatusCode,
+        body: String
+    },
+    AuthenticationRejected {
+        status: reqwest::StatusCode,
+        body: String
+    },
+    AccountInactive(String),
+    /// The client has no user credentials to log in with, e.g. after resuming a session.
+    MissingCredentials,
+    /// A payload could not be decoded; `payload` holds the raw text.
+    Json {
+        source: serde_json::Error,
+        payload: String
+    },
+    Serialize(serde_json::Error),
+    DecryptCredentials(DecryptCredentialsError),
+    Io(std::io::Error),
+    Mqtt(paho_mqtt::Error),
+    Discovery(mdns_sd::Error),
+    Command(CommandError),
+    DeviceNotFound(String),
+    InvalidWifiSsid(String),
+    UnknownProductType(String),
+    InvalidTemperature(String),
+    ConnectionClosed,
+    Timeout
+}
+
+impl DysonError {
+    /// Whether the same call may succeed later without changing its input.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            DysonError::Http(_) | DysonError::Mqtt(_) | DysonError::ConnectionClosed | DysonError::Timeout => true,
+            DysonError::Status { status, .. } => status.is_server_error(),
+            _ => false
+        }
+    }
+}
+
+impl std::fmt::Display for DysonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DysonError::Http(err) => write!(f, "http request failed: {}", err),
+            DysonError::Status { status, body } => write!(f, "dyson api returned {}: {}", status, body),
+            DysonError::AuthenticationRejected { status, .. } => write!(f, "dyson api rejected the credentials ({})", status),
+            DysonError::AccountInactive(status) => write!(f, "dyson account is not active ({})", status),
+            DysonError::MissingCredentials => write!(f, "no user credentials to log in with"),
+            DysonError::Json { source, .. } => write!(f, "unexpected json payload: {}", source),
+            DysonError::Serialize(err) => write!(f, "could not encode json: {}", err),
+            DysonError::DecryptCredentials(err) => err.fmt(f),
+            DysonError::Io(err) => write!(f, "io error: {}", err),
+            DysonError::Mqtt(err) => write!(f, "mqtt error: {}", err),
+            DysonError::Discovery(err) => write!(f, "device discovery failed: {}", err),
+            DysonError::Command(err) => write!(f, "invalid command: {}", err),
+            DysonError::DeviceNotFound(serial) => write!(f, "device {} was not found on the local network", serial),
+            DysonError::InvalidWifiSsid(ssid) => write!(f, "{} is not a Dyson product SSID", ssid),
+            DysonError::UnknownProductType(code) => write!(f, "unknown product type {}", code),
+            DysonError::InvalidTemperature(value) => write!(f, "{} is not a temperature with a unit", value),
+            DysonError::ConnectionClosed => write!(f, "device connection closed"),
+            DysonError::Timeout => write!(f, "timed out waiting for the device"),
+        }
+    }
+}
+
+impl std::error::Error for DysonError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DysonError::Http(err) => Some(err),
+            DysonError::Json { source, .. } => Some(source),
+            DysonError::Serialize(err) => Some(err),
+            DysonError::DecryptCredentials(err) => Some(err),
+            DysonError::Io(err) => Some(err),
+            DysonError::Mqtt(err) => Some(err),
+            DysonError::Discovery(err) => Some(err),
+            DysonError::Command(err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl From<reqwest::Error> for DysonError {
+    fn from(err: reqwest::Error) -> DysonError {
+        DysonError::Http(err)
+    }
+}
+
+impl From<DecryptCredentialsError> for DysonError {
+    fn from(err: DecryptCredentialsError) -> DysonError {
+        DysonError::DecryptCredentials(err)
+    }
+}
+
+impl From<std::io::Error> for DysonError {
+    fn from(err: std::io::Error) -> DysonError {
+        DysonError::Io(err)
+    }
+}
+
+impl From<paho_mqtt::Error> for DysonError {
+    fn from(err: paho_mqtt::Error) -> DysonError {
+        DysonError::Mqtt(err)
+    }
+}
+
+impl From<mdns_sd::Error> for DysonError {
+    fn from(err: mdns_sd::Error) -> DysonError {
+        DysonError::Discovery(err)
+    }
+}
+
+impl From<CommandError> for DysonError {
+    fn from(err: CommandError) -> DysonError {
+        DysonError::Command(err)
+    }
+}
+
+impl From<tokio::time::Elapsed> for DysonError {
+    fn from(_: tokio::time::Elapsed) -> DysonError {
+        DysonError::Timeout
+    }
+}
@@ -0,0 +1,259 @@
+use super::model::*;
+
+/// Air quality indices that can be derived from PM2.5 and PM10 concentrations in µg/m³.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AqiStandard {
+    /// US EPA AQI (2024 PM2.5 breakpoints), 0 to 500.
+    UsEpa,
+    /// European Common Air Quality Index, hourly grid; above 100 means very high.
+    EuCaqi,
+    /// UK Daily Air Quality Index, 1 to 10.
+    UkDaqi,
+    /// China AQI (HJ 633-2012, 24 hour breakpoints), 0 to 500.
+    China
+}
+
+/// An index value with the band it falls in, in the shape the cloud help endpoint uses.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AirQualityIndex {
+    pub standard: AqiStandard,
+    pub value: u32,
+    pub band: EnvironmentRangeHelp
+}
+
+impl AirQualityIndex {
+    pub fn category(&self) -> &str {
+        &self.band.name
+    }
+
+    pub fn color(&self) -> Option<&str> {
+        self.band.color_value.as_deref()
+    }
+}
+
+/// Concentration range mapped linearly onto an index range.
+type Breakpoint = (f32, f32, f32, f32);
+
+/// Highest index value of a band (`None` for the open ended last band), name and colour.
+type Band = (Option<u32>, &'static str, &'static str);
+
+const US_EPA_PM25: [Breakpoint; 6] = [
+    (0.0, 9.0, 0.0, 50.0),
+    (9.1, 35.4, 51.0, 100.0),
+    (35.5, 55.4, 101.0, 150.0),
+    (55.5, 125.4, 151.0, 200.0),
+    (125.5, 225.4, 201.0, 300.0),
+    (225.5, 325.4, 301.0, 500.0),
+];
+
+const US_EPA_PM10: [Breakpoint; 6] = [
+    (0.0, 54.0, 0.0, 50.0),
+    (55.0, 154.0, 51.0, 100.0),
+    (155.0, 254.0, 101.0, 150.0),
+    (255.0, 354.0, 151.0, 200.0),
+    (355.0, 424.0, 201.0, 300.0),
+    (425.0, 604.0, 301.0, 500.0),
+];
+
+const US_EPA_BANDS: [Band; 6] = [
+    (Some(50), "Good", "#00E400"),
+    (Some(100), "Moderate", "#FFFF00"),
+    (Some(150), "Unhealthy for Sensitive Groups", "#FF7E00"),
+    (Some(200), "Unhealthy", "#FF0000"),
+    (Some(300), "Very Unhealthy", "#8F3F97"),
+    (None, "Hazardous", "#7E0023"),
+];
+
+const EU_CAQI_PM25: [Breakpoint; 4] = [
+    (0.0, 15.0, 0.0, 25.0),
+    (15.0, 30.0, 25.0, 50.0),
+    (30.0, 55.0, 50.0, 75.0),
+    (55.0, 110.0, 75.0, 100.0),
+];
+
+const EU_CAQI_PM10: [Breakpoint; 4] = [
+    (0.0, 25.0, 0.0, 25.0),
+    (25.0, 50.0, 25.0, 50.0),
+    (50.0, 90.0, 50.0, 75.0),
+    (90.0, 180.0, 75.0, 100.0),
+];
+
+const EU_CAQI_BANDS: [Band; 5] = [
+    (Some(25), "Very low", "#79BC6A"),
+    (Some(50), "Low", "#BBCF4C"),
+    (Some(75), "Medium", "#EEC20B"),
+    (Some(100), "High", "#F29305"),
+    (None, "Very high", "#E8416F"),
+];
+
+/// Upper concentration of DAQI indices 1 to 9; anything above is index 10.
+const UK_DAQI_PM25: [f32; 9] = [11.0, 23.0, 35.0, 41.0, 47.0, 53.0, 58.0, 64.0, 70.0];
+const UK_DAQI_PM10: [f32; 9] = [16.0, 33.0, 50.0, 58.0, 66.0, 75.0, 83.0, 91.0, 100.0];
+
+const UK_DAQI_BANDS: [Band; 10] = [
+    (Some(1), "Low", "#9CFF9C"),
+    (Some(2), "Low", "#31FF00"),
+    (Some(3), "Low", "#31CF00"),
+    (Some(4), "Moderate", "#FFFF00"),
+    (Some(5), "Moderate", "#FFCF00"),
+    (Some(6), "Moderate", "#FF9A00"),
+    (Some(7), "High", "#FF6464"),
+    (Some(8), "High", "#FF0000"),
+    (Some(9), "High", "#990000"),
+    (None, "Very High", "#CE30FF"),
+];
+
+const CHINA_PM25: [Breakpoint; 7] = [
+    (0.0, 35.0, 0.0, 50.0),
+    (35.0, 75.0, 50.0, 100.0),
+    (75.0, 115.0, 100.0, 150.0),
+    (115.0, 150.0, 150.0, 200.0),
+    (150.0, 250.0, 200.0, 300.0),
+    (250.0, 350.0, 300.0, 400.0),
+    (350.0, 500.0, 400.0, 500.0),
+];
+
+const CHINA_PM10: [Breakpoint; 7] = [
+    (0.0, 50.0, 0.0, 50.0),
+    (50.0, 150.0, 50.0, 100.0),
+    (150.0, 250.0, 100.0, 150.0),
+    (250.0, 350.0, 150.0, 200.0),
+    (350.0, 420.0, 200.0, 300.0),
+    (420.0, 500.0, 300.0, 400.0),
+    (500.0, 600.0, 400.0, 500.0),
+];
+
+const CHINA_BANDS: [Band; 6] = [
+    (Some(50), "Excellent", "#00E400"),
+    (Some(100), "Good", "#FFFF00"),
+    (Some(150), "Lightly Polluted", "#FF7E00"),
+    (Some(200), "Moderately Polluted", "#FF0000"),
+    (Some(300), "Heavily Polluted", "#99004C"),
+    (None, "Severely Polluted", "#7E0023"),
+];
+
+impl AqiStandard {
+    /// The worst of the PM2.5 and PM10 sub-indices; `None` without any concentration.
+    pub fn calculate(self, pm25: Option<f32>, pm10: Option<f32>) -> Option<AirQualityIndex> {
+        let value = match self {
+            AqiStandard::UsEpa => worst(
+                pm25.map(|pm25| interpolate(&US_EPA_PM25, (pm25 * 10.0).floor() / 10.0).round().min(500.0)),
+                pm10.map(|pm10| interpolate(&US_EPA_PM10, pm10.floor()).round().min(500.0))),
+            AqiStandard::EuCaqi => worst(
+                pm25.map(|pm25| interpolate(&EU_CAQI_PM25, pm25).round()),
+                pm10.map(|pm10| interpolate(&EU_CAQI_PM10, pm10).round())),
+            AqiStandard::UkDaqi => worst(
+                pm25.map(|pm25| daqi(&UK_DAQI_PM25, pm25)),
+                pm10.map(|pm10| daqi(&UK_DAQI_PM10, pm10))),
+            AqiStandard::China => worst(
+                pm25.map(|pm25| interpolate(&CHINA_PM25, pm25).ceil().min(500.0)),
+                pm10.map(|pm10| interpolate(&CHINA_PM10, pm10).ceil().min(500.0)))
+        }? as u32;
+
+        // the last band is open ended, so every value has one
+        let position = self.band_table().0.iter()
+            .position(|(high, _, _)| high.map_or(true, |high| value <= high))
+            .unwrap();
+        let band = self.bands().swap_remove(position);
+
+        Some(AirQualityIndex { standard: self, value, band })
+    }
+
+    pub fn bands(self) -> Vec<EnvironmentRangeHelp> {
+        let (bands, first) = self.band_table();
+
+        let mut low = first;
+        bands.iter().enumerate()
+            .map(|(position, (high, name, color))| {
+                let range = match high {
+                    Some(high) if *high == low => high.to_string(),
+                    Some(high) => format!("{}-{}", low, high),
+                    None => format!("{}+", low)
+                };
+                low = high.map_or(low, |high| high + 1);
+
+                EnvironmentRangeHelp {
+                    range,
+                    name: String::from(*name),
+                    description: String::new(),
+                    color_index: (position + 1).to_string(),
+                    color_value: Some(String::from(*color))
+                }
+            })
+            .collect()
+    }
+
+    /// The bands with the lowest index value of the first one.
+    fn band_table(self) -> (&'static [Band], u32) {
+        match self {
+            AqiStandard::UsEpa => (&US_EPA_BANDS, 0),
+            AqiStandard::EuCaqi => (&EU_CAQI_BANDS, 0),
+            AqiStandard::UkDaqi => (&UK_DAQI_BANDS, 1),
+            AqiStandard::China => (&CHINA_BANDS, 0)
+        }
+    }
+}
+
+fn worst(a: Option<f32>, b: Option<f32>) -> Option<f32> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.max(b)),
+        (a, b) => a.or(b)
+    }
+}
+
+/// Continues the last segment for concentrations above the table.
+fn interpolate(breakpoints: &[Breakpoint], concentration: f32) -> f32 {
+    let concentration = concentration.max(0.0);
+    let (low_concentration, high_concentration, low_index, high_index) = *breakpoints.iter()
+        .find(|(_, high_concentration, _, _)| concentration <= *high_concentration)
+        .unwrap_or_else(|| breakpoints.last().unwrap());
+
+    low_index + (high_index - low_index) / (high_concentration - low_concentration) * (concentration - low_concentration)
+}
+
+fn daqi(upper_bounds: &[f32], concentration: f32) -> f32 {
+    let concentration = concentration.round();
+
+    upper_bounds.iter()
+        .position(|upper_bound| concentration <= *upper_bound)
+        .map_or(upper_bounds.len() + 1, |position| position + 1) as f32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_calculates_indices_from_particulates() {
+        let samples = vec![
+            (AqiStandard::UsEpa, Some(12.0), Some(20.0), 56, "Moderate", "#FFFF00"),
+            (AqiStandard::UsEpa, None, Some(700.0), 500, "Hazardous", "#7E0023"),
+            (AqiStandard::EuCaqi, Some(20.0), Some(30.0), 33, "Low", "#BBCF4C"),
+            (AqiStandard::EuCaqi, Some(220.0), None, 150, "Very high", "#E8416F"),
+            (AqiStandard::UkDaqi, Some(40.0), Some(10.0), 4, "Moderate", "#FFFF00"),
+            (AqiStandard::UkDaqi, Some(80.0), None, 10, "Very High", "#CE30FF"),
+            (AqiStandard::China, Some(80.0), Some(100.0), 107, "Lightly Polluted", "#FF7E00"),
+        ];
+
+        for (standard, pm25, pm10, value, category, color) in samples {
+            let actual = standard.calculate(pm25, pm10).unwrap();
+
+            assert_eq!(actual.value, value, "{:?}", standard);
+            assert_eq!(actual.category(), category);
+            assert_eq!(actual.color(), Some(color));
+        }
+
+        assert_eq!(AqiStandard::China.calculate(None, None), None);
+    }
+
+    #[test]
+    fn it_describes_bands_like_the_cloud_help() {
+        let bands = AqiStandard::UsEpa.bands();
+
+        assert_eq!(bands.len(), 6);
+        assert_eq!(bands[1].range, "51-100");
+        assert_eq!(bands[1].color_index, "2");
+        assert_eq!(bands[5].range, "301+");
+        assert_eq!(AqiStandard::UkDaqi.bands()[0].range, "1");
+    }
+}
@@ -0,0 +1,145 @@
+use super::units::*;
+
+/// Relative humidity in percent below which air counts as too dry.
+pub const COMFORT_HUMIDITY_MIN: f32 = 30.0;
+/// Relative humidity in percent above which air counts as too humid.
+pub const COMFORT_HUMIDITY_MAX: f32 = 60.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Comfort {
+    TooDry,
+    Comfortable,
+    TooHumid
+}
+
+impl Comfort {
+    pub fn from_humidity(humidity_percentage: f32) -> Self {
+        if humidity_percentage < COMFORT_HUMIDITY_MIN {
+            Comfort::TooDry
+        } else if humidity_percentage > COMFORT_HUMIDITY_MAX {
+            Comfort::TooHumid
+        } else {
+            Comfort::Comfortable
+        }
+    }
+}
+
+/// Magnus formula; `None` without any humidity.
+pub fn dew_point_kelvin(temperature_kelvin: f32, humidity_percentage: f32) -> Option<f32> {
+    if humidity_percentage <= 0.0 {
+        return None;
+    }
+
+    let (a, b) = (17.62, 243.12);
+    let celsius = kelvin_to_celsius(temperature_kelvin);
+    let gamma = (humidity_percentage / 100.0).ln() + a * celsius / (b + celsius);
+
+    Some(celsius_to_kelvin(b * gamma / (a - gamma)))
+}
+
+/// Water vapour in g/m³.
+pub fn absolute_humidity(temperature_kelvin: f32, humidity_percentage: f32) -> f32 {
+    let celsius = kelvin_to_celsius(temperature_kelvin);
+    let saturation_pressure = 6.112 * (17.67 * celsius / (celsius + 243.5)).exp();
+
+    saturation_pressure * humidity_percentage * 2.1674 / temperature_kelvin
+}
+
+/// US National Weather Service heat index, using its simple formula below 80 °F.
+pub fn heat_index_kelvin(temperature_kelvin: f32, humidity_percentage: f32) -> f32 {
+    let t = kelvin_to_fahrenheit(temperature_kelvin);
+    let rh = humidity_percentage;
+
+    let simple = 0.5 * (t + 61.0 + (t - 68.0) * 1.2 + rh * 0.094);
+    if (simple + t) / 2.0 < 80.0 {
+        return fahrenheit_to_kelvin(simple);
+    }
+
+    let mut index = -42.379 + 2.049_015_3 * t + 10.143_331 * rh - 0.224_755_4 * t * rh
+        - 0.006_837_83 * t * t - 0.054_817_17 * rh * rh + 0.001_228_74 * t * t * rh
+        + 0.000_852_82 * t * rh * rh - 0.000_001_99 * t * t * rh * rh;
+
+    if rh < 13.0 && (80.0..=112.0).contains(&t) {
+        index -= (13.0 - rh) / 4.0 * ((17.0 - (t - 95.0).abs()) / 17.0).sqrt();
+    } else if rh > 85.0 && (80.0..=87.0).contains(&t) {
+        index += (rh - 85.0) / 10.0 * (87.0 - t) / 5.0;
+    }
+
+    fahrenheit_to_kelvin(index)
+}
+
+/// Canadian humidex, a dimensionless value on the Celsius scale; `None` without any humidity.
+pub fn humidex(temperature_kelvin: f32, humidity_percentage: f32) -> Option<f32> {
+    let dew_point = dew_point_kelvin(temperature_kelvin, humidity_percentage)?;
+    let vapour_pressure = 6.11 * (5417.753 * (1.0 / 273.16 - 1.0 / dew_point)).exp();
+
+    Some(kelvin_to_celsius(temperature_kelvin) + 0.5555 * (vapour_pressure - 10.0))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mqtt::*;
+    use crate::state::SensorReading;
+
+    fn reading(celsius: f32, humidity_percentage: f32) -> SensorReading {
+        SensorReading {
+            temperature: Some(Temperature::from_celsius(celsius)),
+            humidity_percentage: Some(humidity_percentage),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn it_derives_comfort_metrics() {
+        let reading = reading(30.0, 50.0);
+
+        assert!((kelvin_to_celsius(reading.dew_point_kelvin().unwrap()) - 18.44).abs() < 0.01);
+        assert!((reading.absolute_humidity().unwrap() - 15.18).abs() < 0.01);
+        assert!((kelvin_to_celsius(reading.heat_index_kelvin().unwrap()) - 31.05).abs() < 0.05);
+        assert!((reading.humidex().unwrap() - 36.33).abs() < 0.05);
+        assert_eq!(reading.comfort(), Some(Comfort::Comfortable));
+
+        assert!((heat_index_kelvin(celsius_to_kelvin(20.0), 40.0) - celsius_to_kelvin(20.0)).abs() < 1.0);
+        assert_eq!(dew_point_kelvin(celsius_to_kelvin(20.0), 0.0), None);
+        assert_eq!(Comfort::from_humidity(25.0), Comfort::TooDry);
+        assert_eq!(Comfort::from_humidity(75.0), Comfort::TooHumid);
+    }
+
+    fn link_sensor_data(tact: &str, hact: &str) -> EnvironmentalCurrentSensorDataRaw {
+        EnvironmentalCurrentSensorDataRaw {
+            tact: String::from(tact),
+            hact: String::from(hact),
+            pact: String::from("0001"),
+            vact: String::from("0002"),
+            sltm: String::from("OFF")
+        }
+    }
+
+    #[test]
+    fn it_derives_comfort_metrics_from_link_sensor_data() {
+        let data = EnvironmentCurrentSensorData::from_raw(&link_sensor_data("3032", "0050"));
+
+        assert!((kelvin_to_celsius(data.dew_point_kelvin().unwrap()) - 18.44).abs() < 0.1);
+        assert_eq!(data.absolute_humidity(), SensorReading::from(&link_sensor_data("3032", "0050")).absolute_humidity());
+        assert_eq!(data.comfort(), Some(Comfort::Comfortable));
+    }
+
+    #[test]
+    fn it_skips_comfort_metrics_while_sensors_warm_up() {
+        for raw in [link_sensor_data("INIT", "0050"), link_sensor_data("3032", "INIT")].iter() {
+            let data = EnvironmentCurrentSensorData::from_raw(raw);
+            let reading = SensorReading::from(raw);
+
+            assert_eq!(data.dew_point_kelvin(), None);
+            assert_eq!(data.absolute_humidity(), None);
+            assert_eq!(data.heat_index_kelvin(), None);
+            assert_eq!(data.humidex(), None);
+            assert_eq!(reading.dew_point_kelvin(), None);
+            assert_eq!(reading.heat_index_kelvin(), None);
+        }
+
+        assert_eq!(EnvironmentCurrentSensorData::from_raw(&link_sensor_data("3032", "INIT")).comfort(), None);
+        assert_eq!(SensorReading::default().comfort(), None);
+    }
+}
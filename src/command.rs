@@ -0,0 +1,774 @@
+use std::convert::TryFrom;
+
+use serde::{Serialize, Serializer};
+
+use super::api_error::*;
+use super::control::*;
+use super::mqtt::*;
+use super::product::*;
+use super::units::*;
+use super::util::*;
+
+pub const HEAT_TARGET_MIN_KELVIN: f32 = 274.15;
+pub const HEAT_TARGET_MAX_KELVIN: f32 = 310.15;
+pub const OSCILLATION_ANGLE_MIN: u16 = 5;
+pub const OSCILLATION_ANGLE_MAX: u16 = 355;
+pub const OSCILLATION_ANGLE_MIN_SPAN: u16 = 30;
+pub const HUMIDITY_TARGET_MIN: u8 = 30;
+pub const HUMIDITY_TARGET_MAX: u8 = 70;
+pub const SLEEP_TIMER_MIN_MINUTES: u16 = 1;
+pub const SLEEP_TIMER_MAX_MINUTES: u16 = 540;
+
+const OSCILLATION_PRESET_CUSTOM: &str = "CUST";
+
+const HEAT_TARGET_TOLERANCE_KELVIN: f32 = 0.05;
+
+#[derive(Serialize, Clone, Debug, PartialEq, Default)]
+pub struct StateSetData {
+    #[serde(rename = "fmod", skip_serializing_if = "Option::is_none")]
+    pub fan_mode: Option<FanMode>,
+    #[serde(rename = "fnsp", skip_serializing_if = "Option::is_none")]
+    pub fan_speed: Option<FanSpeed>,
+    #[serde(rename = "qtar", skip_serializing_if = "Option::is_none")]
+    pub quality_target: Option<QualityTarget>,
+    #[serde(rename = "oson", skip_serializing_if = "Option::is_none")]
+    pub oscillation_status: Option<OscillationStatus>,
+    #[serde(rename = "rhtm", skip_serializing_if = "Option::is_none")]
+    pub air_quality_monitoring_status: Option<AirQualityMonitoringStatus>,
+    #[serde(rename = "nmod", skip_serializing_if = "Option::is_none")]
+    pub night_mode: Option<NightMode>,
+    #[serde(rename = "hmod", skip_serializing_if = "Option::is_none")]
+    pub heat_mode: Option<HeatMode>,
+    #[serde(rename = "hmax", skip_serializing_if = "Option::is_none")]
+    #[serde(serialize_with = "to_raw_kelvin_string")]
+    pub heat_target_kelvin: Option<f32>,
+    #[serde(rename = "ffoc", skip_serializing_if = "Option::is_none")]
+    pub fan_focus_mode: Option<FanFocusMode>,
+    #[serde(rename = "fpwr", skip_serializing_if = "Option::is_none")]
+    pub fan_power: Option<FanPower>,
+    #[serde(rename = "auto", skip_serializing_if = "Option::is_none")]
+    pub auto_mode: Option<AutoMode>,
+    #[serde(rename = "osal", skip_serializing_if = "Option::is_none")]
+    #[serde(serialize_with = "to_padded_string")]
+    pub oscillation_angle_low: Option<u16>,
+    #[serde(rename = "osau", skip_serializing_if = "Option::is_none")]
+    #[serde(serialize_with = "to_padded_string")]
+    pub oscillation_angle_high: Option<u16>,
+    #[serde(rename = "ancp", skip_serializing_if = "Option::is_none")]
+    pub oscillation_preset: Option<String>,
+    #[serde(rename = "fdir", skip_serializing_if = "Option::is_none")]
+    pub front_airflow: Option<FrontAirflow>,
+    #[serde(rename = "hume", skip_serializing_if = "Option::is_none")]
+    pub humidify_mode: Option<HumidifyMode>,
+    #[serde(rename = "haut", skip_serializing_if = "Option::is_none")]
+    pub auto_humidity: Option<AutoHumidity>,
+    #[serde(rename = "humt", skip_serializing_if = "Option::is_none")]
+    #[serde(serialize_with = "to_padded_string")]
+    pub humidity_target: Option<u8>,
+    #[serde(rename = "wath", skip_serializing_if = "Option::is_none")]
+    pub water_hardness: Option<WaterHardness>,
+    #[serde(rename = "clcr", skip_serializing_if = "Option::is_none")]
+    pub clean_cycle: Option<CleanCycle>,
+    #[serde(rename = "sltm", skip_serializing_if = "Option::is_none")]
+    pub sleep_timer: Option<SleepTimer>,
+}
+
+impl StateSetData {
+    pub fn requested_fields(&self) -> Vec<ProductStateField> {
+        self.compare(&Default::default()).into_iter()
+            .filter(|(_, requested, _)| *requested)
+            .map(|(field, _, _)| field)
+            .collect()
+    }
+
+    pub fn matching_fields(&self, observed: &StateSetData) -> Vec<ProductStateField> {
+        self.compare(observed).into_iter()
+            .filter(|(_, requested, matching)| *requested && *matching)
+            .map(|(field, _, _)| field)
+            .collect()
+    }
+
+    fn compare(&self, observed: &StateSetData) -> Vec<(ProductStateField, bool, bool)> {
+        vec![
+            compare(ProductStateField::FanMode, &self.fan_mode, &observed.fan_mode),
+            compare(ProductStateField::FanSpeed, &self.fan_speed, &observed.fan_speed),
+            compare(ProductStateField::QualityTarget, &self.quality_target, &observed.quality_target),
+            compare(ProductStateField::OscillationStatus, &self.oscillation_status, &observed.oscillation_status),
+            compare(ProductStateField::AirQualityMonitoringStatus, &self.air_quality_monitoring_status, &observed.air_quality_monitoring_status),
+            compare(ProductStateField::NightMode, &self.night_mode, &observed.night_mode),
+            compare(ProductStateField::HeatMode, &self.heat_mode, &observed.heat_mode),
+            compare(ProductStateField::HeatTarget, &self.heat_target_kelvin.map(to_deci_kelvin), &observed.heat_target_kelvin.map(to_deci_kelvin)),
+            compare(ProductStateField::FanFocusMode, &self.fan_focus_mode, &observed.fan_focus_mode),
+            compare(ProductStateField::FanPower, &self.fan_power, &observed.fan_power),
+            compare(ProductStateField::AutoMode, &self.auto_mode, &observed.auto_mode),
+            compare(ProductStateField::OscillationAngleLow, &self.oscillation_angle_low, &observed.oscillation_angle_low),
+            compare(ProductStateField::OscillationAngleHigh, &self.oscillation_angle_high, &observed.oscillation_angle_high),
+            compare(ProductStateField::OscillationPreset, &self.oscillation_preset, &observed.oscillation_preset),
+            compare(ProductStateField::FrontAirflow, &self.front_airflow, &observed.front_airflow),
+            compare(ProductStateField::HumidifyMode, &self.humidify_mode, &observed.humidify_mode),
+            compare(ProductStateField::AutoHumidity, &self.auto_humidity, &observed.auto_humidity),
+            compare(ProductStateField::HumidityTarget, &self.humidity_target, &observed.humidity_target),
+            compare(ProductStateField::WaterHardness, &self.water_hardness, &observed.water_hardness),
+            compare(ProductStateField::CleanCycle, &self.clean_cycle, &observed.clean_cycle),
+            compare_sleep_timer(&self.sleep_timer, &observed.sleep_timer),
+        ]
+    }
+}
+
+impl From<&ProductState> for StateSetData {
+    fn from(state: &ProductState) -> Self {
+        StateSetData {
+            fan_mode: Some(state.fan_mode.clone()),
+            fan_speed: Some(state.fan_speed.clone()),
+            quality_target: Some(state.quality_target.clone()),
+            oscillation_status: Some(state.oscillation_status.clone()),
+            air_quality_monitoring_status: Some(state.air_quality_monitoring_status.clone()),
+            night_mode: Some(state.night_mode.clone()),
+            heat_mode: Some(state.heat_mode.clone()),
+            heat_target_kelvin: Some(state.heat_target_kelvin),
+            fan_focus_mode: Some(state.fan_focus_mode.clone()),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&ProductStateChange> for StateSetData {
+    fn from(change: &ProductStateChange) -> Self {
+        StateSetData {
+            fan_mode: new_value(&change.fan_mode),
+            fan_speed: new_value(&change.fan_speed),
+            quality_target: new_value(&change.quality_target),
+            oscillation_status: new_value(&change.oscillation_status),
+            air_quality_monitoring_status: new_value(&change.air_quality_monitoring_status),
+            night_mode: new_value(&change.night_mode),
+            heat_mode: new_value(&change.heat_mode),
+            heat_target_kelvin: new_value(&change.heat_target_kelvin),
+            fan_focus_mode: new_value(&change.fan_focus_mode),
+            ..Default::default()
+        }
+    }
+}
+
+/// The Link generation reports its sleep timer only with the environmental sensor data.
+impl From<&EnvironmentalCurrentSensorDataRaw> for StateSetData {
+    fn from(data: &EnvironmentalCurrentSensorDataRaw) -> Self {
+        StateSetData {
+            sleep_timer: Some(to_sleep_timer(data.sltm.parse().ok())),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&PurifierProductState> for StateSetData {
+    fn from(state: &PurifierProductState) -> Self {
+        StateSetData {
+            fan_speed: Some(state.fan_speed.clone()),
+            oscillation_status: Some(state.oscillation_status.clone()),
+            air_quality_monitoring_status: Some(state.air_quality_monitoring_status.clone()),
+            night_mode: Some(state.night_mode.clone()),
+            heat_mode: state.heat_mode.clone(),
+            heat_target_kelvin: state.heat_target_kelvin,
+            fan_power: Some(state.fan_power.clone()),
+            auto_mode: Some(state.auto_mode.clone()),
+            oscillation_angle_low: state.oscillation_angle_low,
+            oscillation_angle_high: state.oscillation_angle_high,
+            oscillation_preset: state.oscillation_preset.clone(),
+            front_airflow: state.front_airflow.clone(),
+            humidify_mode: state.humidify_mode.clone(),
+            auto_humidity: state.auto_humidity.clone(),
+            humidity_target: state.humidity_target,
+            water_hardness: state.water_hardness.clone(),
+            clean_cycle: state.clean_cycle.clone(),
+            sleep_timer: Some(to_sleep_timer(state.sleep_timer)),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&PurifierProductStateChange> for StateSetData {
+    fn from(change: &PurifierProductStateChange) -> Self {
+        StateSetData {
+            fan_speed: new_value(&change.fan_speed),
+            oscillation_status: new_value(&change.oscillation_status),
+            air_quality_monitoring_status: new_value(&change.air_quality_monitoring_status),
+            night_mode: new_value(&change.night_mode),
+            heat_mode: new_value(&change.heat_mode).flatten(),
+            heat_target_kelvin: new_value(&change.heat_target_kelvin).flatten(),
+            fan_power: new_value(&change.fan_power),
+            auto_mode: new_value(&change.auto_mode),
+            oscillation_angle_low: new_value(&change.oscillation_angle_low).flatten(),
+            oscillation_angle_high: new_value(&change.oscillation_angle_high).flatten(),
+            oscillation_preset: new_value(&change.oscillation_preset).flatten(),
+            front_airflow: new_value(&change.front_airflow).flatten(),
+            humidify_mode: new_value(&change.humidify_mode).flatten(),
+            auto_humidity: new_value(&change.auto_humidity).flatten(),
+            humidity_target: new_value(&change.humidity_target).flatten(),
+            water_hardness: new_value(&change.water_hardness).flatten(),
+            clean_cycle: new_value(&change.clean_cycle).flatten(),
+            sleep_timer: new_value(&change.sleep_timer).map(to_sleep_timer),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct StateSetPayload {
+    #[serde(rename = "msg")]
+    pub message: String,
+    pub time: String,
+    #[serde(rename = "mode-reason")]
+    pub mode_reason: String,
+    pub data: StateSetData
+}
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct StateSetCommand {
+    data: StateSetData
+}
+
+impl StateSetCommand {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn fan_mode(mut self, fan_mode: FanMode) -> Self {
+        self.data.fan_mode = Some(fan_mode);
+        self
+    }
+
+    pub fn fan_speed(mut self, fan_speed: FanSpeed) -> Self {
+        self.data.fan_speed = Some(fan_speed);
+        self
+    }
+
+    pub fn quality_target(mut self, quality_target: QualityTarget) -> Self {
+        self.data.quality_target = Some(quality_target);
+        self
+    }
+
+    pub fn oscillation(mut self, oscillation_status: OscillationStatus) -> Self {
+        self.data.oscillation_status = Some(oscillation_status);
+        self
+    }
+
+    pub fn air_quality_monitoring(mut self, status: AirQualityMonitoringStatus) -> Self {
+        self.data.air_quality_monitoring_status = Some(status);
+        self
+    }
+
+    pub fn night_mode(mut self, night_mode: NightMode) -> Self {
+        self.data.night_mode = Some(night_mode);
+        self
+    }
+
+    pub fn heat_mode(mut self, heat_mode: HeatMode) -> Self {
+        self.data.heat_mode = Some(heat_mode);
+        self
+    }
+
+    pub fn heat_target_kelvin(mut self, kelvin: f32) -> Self {
+        self.data.heat_target_kelvin = Some(kelvin);
+        self
+    }
+
+    /// The device accepts 1 to 37 °C.
+    pub fn heat_target_celsius(self, celsius: f32) -> Self {
+        self.heat_target_kelvin(celsius_to_kelvin(celsius))
+    }
+
+    pub fn heat_target_fahrenheit(self, fahrenheit: f32) -> Self {
+        self.heat_target_kelvin(fahrenheit_to_kelvin(fahrenheit))
+    }
+
+    pub fn heat_target(self, temperature: Temperature) -> Self {
+        self.heat_target_kelvin(temperature.kelvin())
+    }
+
+    pub fn fan_focus_mode(mut self, fan_focus_mode: FanFocusMode) -> Self {
+        self.data.fan_focus_mode = Some(fan_focus_mode);
+        self
+    }
+
+    pub fn fan_power(mut self, fan_power: FanPower) -> Self {
+        self.data.fan_power = Some(fan_power);
+        self
+    }
+
+    pub fn auto_mode(mut self, auto_mode: AutoMode) -> Self {
+        self.data.auto_mode = Some(auto_mode);
+        self
+    }
+
+    /// Sweeps between the two angles in degrees, switching to the custom angle preset.
+    pub fn oscillation_angles(mut self, low: u16, high: u16) -> Self {
+        self.data.oscillation_angle_low = Some(low);
+        self.data.oscillation_angle_high = Some(high);
+        self.data.oscillation_preset = Some(String::from(OSCILLATION_PRESET_CUSTOM));
+        self
+    }
+
+    pub fn front_airflow(mut self, front_airflow: FrontAirflow) -> Self {
+        self.data.front_airflow = Some(front_airflow);
+        self
+    }
+
+    pub fn humidify(mut self, humidify_mode: HumidifyMode) -> Self {
+        self.data.humidify_mode = Some(humidify_mode);
+        self
+    }
+
+    pub fn auto_humidity(mut self, auto_humidity: AutoHumidity) -> Self {
+        self.data.auto_humidity = Some(auto_humidity);
+        self
+    }
+
+    /// Relative humidity in percent, between 30 and 70.
+    pub fn humidity_target(mut self, percent: u8) -> Self {
+        self.data.humidity_target = Some(percent);
+        self
+    }
+
+    pub fn water_hardness(mut self, water_hardness: WaterHardness) -> Self {
+        self.data.water_hardness = Some(water_hardness);
+        self
+    }
+
+    pub fn start_deep_clean(mut self) -> Self {
+        self.data.clean_cycle = Some(CleanCycle::Active);
+        self
+    }
+
+    pub fn cancel_deep_clean(mut self) -> Self {
+        self.data.clean_cycle = Some(CleanCycle::Idle);
+        self
+    }
+
+    /// Turns the device off after the given minutes, between 1 and 540.
+    pub fn sleep_timer(mut self, minutes: u16) -> Self {
+        self.data.sleep_timer = Some(SleepTimer::Minutes(minutes));
+        self
+    }
+
+    pub fn cancel_sleep_timer(mut self) -> Self {
+        self.data.sleep_timer = Some(SleepTimer::Off);
+        self
+    }
+
+    pub fn data(&self) -> &StateSetData {
+        &self.data
+    }
+
+    pub fn validate(&self) -> Result<(), CommandError> {
+        if self.data == StateSetData::default() {
+            return Err(CommandError::Empty);
+        }
+
+        if let Some(kelvin) = self.data.heat_target_kelvin {
+            let range = (HEAT_TARGET_MIN_KELVIN - HEAT_TARGET_TOLERANCE_KELVIN)..=(HEAT_TARGET_MAX_KELVIN + HEAT_TARGET_TOLERANCE_KELVIN);
+            if !range.contains(&kelvin) {
+                return Err(CommandError::HeatTargetOutOfRange(kelvin));
+            }
+        }
+
+        if let (Some(low), Some(high)) = (self.data.oscillation_angle_low, self.data.oscillation_angle_high) {
+            if low < OSCILLATION_ANGLE_MIN || high > OSCILLATION_ANGLE_MAX || high < low || high - low < OSCILLATION_ANGLE_MIN_SPAN {
+                return Err(CommandError::OscillationAnglesOutOfRange(low, high));
+            }
+        }
+
+        if let Some(percent) = self.data.humidity_target {
+            if !(HUMIDITY_TARGET_MIN..=HUMIDITY_TARGET_MAX).contains(&percent) {
+                return Err(CommandError::HumidityTargetOutOfRange(percent));
+            }
+        }
+
+        if let Some(SleepTimer::Minutes(minutes)) = self.data.sleep_timer {
+            if !(SLEEP_TIMER_MIN_MINUTES..=SLEEP_TIMER_MAX_MINUTES).contains(&minutes) {
+                return Err(CommandError::SleepTimerOutOfRange(minutes));
+            }
+        }
+
+        if self.data.clean_cycle == Some(CleanCycle::Complete) {
+            return Err(CommandError::ConflictingSettings("a deep clean cycle can only be started or cancelled"));
+        }
+
+        match (&self.data.fan_mode, &self.data.fan_speed) {
+            (Some(FanMode::Off), Some(_)) =>
+                Err(CommandError::ConflictingSettings("fan speed cannot be set while turning the fan off")),
+            (Some(FanMode::Fan), Some(FanSpeed::Auto)) =>
+                Err(CommandError::ConflictingSettings("automatic fan speed requires the automatic fan mode")),
+            _ => Ok(())
+        }
+    }
+
+    /// Also rejects settings the given product doesn't have.
+    pub fn validate_for(&self, product_type: ProductType) -> Result<(), CommandError> {
+        self.validate()?;
+
+        let data = &self.data;
+        let capabilities = product_type.capabilities();
+        let heating = data.heat_mode.is_some() || data.heat_target_kelvin.is_some();
+        let angles = data.oscillation_angle_low.is_some() || data.oscillation_angle_high.is_some();
+        let humidifier = data.humidify_mode.is_some() || data.auto_humidity.is_some() || data.humidity_target.is_some()
+            || data.water_hardness.is_some() || data.clean_cycle.is_some();
+        let link_only = data.fan_mode.is_some() || data.quality_target.is_some() || data.fan_focus_mode.is_some();
+        let purifier_only = data.fan_power.is_some() || data.auto_mode.is_some() || data.front_airflow.is_some() || angles || humidifier;
+
+        let unsupported = match product_type.generation() {
+            ProductGeneration::Link if purifier_only => Some("settings of the purifier generation"),
+            ProductGeneration::Purifier if link_only => Some("settings of the Link generation"),
+            ProductGeneration::Robot => Some("fan settings"),
+            _ if heating && !capabilities.heating => Some("heating"),
+            _ if angles && !capabilities.oscillation_angles => Some("oscillation angles"),
+            _ if humidifier && !capabilities.humidifier => Some("humidification"),
+            _ => None
+        };
+
+        match unsupported {
+            Some(setting) => Err(CommandError::Unsupported { product_type, setting }),
+            None => Ok(())
+        }
+    }
+
+    pub fn to_payload(&self) -> Result<StateSetPayload, CommandError> {
+        self.validate()?;
+
+        Ok(StateSetPayload {
+            message: String::from("STATE-SET"),
+            time: current_timestamp(),
+            mode_reason: String::from("LAPP"),
+            data: self.data.clone()
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CommandAcknowledgement {
+    pub confirmed: Vec<ProductStateField>,
+    pub ignored: Vec<ProductStateField>
+}
+
+/// Collects the states reported after a command until every requested field is confirmed.
+#[derive(Clone, Debug)]
+pub struct CommandConfirmation {
+    requested: StateSetData,
+    confirmed: Vec<ProductStateField>,
+    observed: bool
+}
+
+impl CommandConfirmation {
+    pub fn new(command: &StateSetCommand) -> Self {
+        CommandConfirmation {
+            requested: command.data.clone(),
+            confirmed: vec![],
+            observed: false
+        }
+    }
+
+    /// States that carry none of the requested fields, like unrelated sensor data, are not counted.
+    pub fn observe(&mut self, state: &StateSetData) -> bool {
+        let reported = state.requested_fields();
+        if self.requested.requested_fields().iter().any(|field| reported.contains(field)) {
+            self.observed = true;
+        }
+
+        for field in self.requested.matching_fields(state) {
+            if !self.confirmed.contains(&field) {
+                self.confirmed.push(field);
+            }
+        }
+
+        self.is_complete()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.requested.requested_fields().iter().all(|field| self.confirmed.contains(field))
+    }
+
+    /// Returns `None` when the device has not reported any requested field since the command was sent.
+    pub fn acknowledgement(&self) -> Option<CommandAcknowledgement> {
+        if !self.observed {
+            return None;
+        }
+
+        let requested = self.requested.requested_fields();
+
+        Some(CommandAcknowledgement {
+            confirmed: requested.iter().filter(|field| self.confirmed.contains(field)).cloned().collect(),
+            ignored: requested.iter().filter(|field| !self.confirmed.contains(field)).cloned().collect()
+        })
+    }
+}
+
+fn compare<T: PartialEq>(field: ProductStateField, requested: &Option<T>, observed: &Option<T>) -> (ProductStateField, bool, bool) {
+    (field, requested.is_some(), requested.is_some() && requested == observed)
+}
+
+/// The device counts the timer down right away, so a report one minute short still matches.
+fn compare_sleep_timer(requested: &Option<SleepTimer>, observed: &Option<SleepTimer>) -> (ProductStateField, bool, bool) {
+    let matching = match (requested, observed) {
+        (Some(SleepTimer::Minutes(requested)), Some(SleepTimer::Minutes(observed))) =>
+            (requested.saturating_sub(1)..=*requested).contains(observed),
+        (Some(SleepTimer::Off), Some(SleepTimer::Off)) => true,
+        _ => false
+    };
+
+    (ProductStateField::SleepTimer, requested.is_some(), matching)
+}
+
+fn new_value<T: Clone>(change: &Option<ValueChange<T>>) -> Option<T> {
+    change.as_ref().map(|change| change.new.clone())
+}
+
+fn to_sleep_timer(minutes: Option<u32>) -> SleepTimer {
+    match minutes {
+        Some(minutes) if minutes > 0 => SleepTimer::Minutes(u16::try_from(minutes).unwrap_or(u16::MAX)),
+        _ => SleepTimer::Off
+    }
+}
+
+fn to_padded_string<T, S>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error>
+    where T: std::fmt::Display, S: Serializer
+{
+    match value {
+        Some(value) => serializer.serialize_str(&format!("{:0>4}", value)),
+        None => serializer.serialize_none()
+    }
+}
+
+fn to_raw_kelvin_string<S>(kelvin: &Option<f32>, serializer: S) -> Result<S::Ok, S::Error>
+    where S: Serializer
+{
+    match kelvin {
+        Some(kelvin) => serializer.serialize_str(&format_deci_kelvin(*kelvin)),
+        None => serializer.serialize_none()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_serializes_only_requested_settings() {
+        let command = StateSetCommand::new()
+            .fan_mode(FanMode::Fan)
+            .fan_speed(FanSpeed::Speed_4)
+            .fan_focus_mode(FanFocusMode::Focus)
+            .heat_mode(HeatMode::On)
+            .heat_target_kelvin(298.15);
+
+        let payload = command.to_payload().unwrap();
+        let actual = serde_json::to_value(&payload).unwrap();
+
+        let expected = serde_json::json!({
+            "msg": "STATE-SET",
+            "time": payload.time,
+            "mode-reason": "LAPP",
+            "data": {
+                "fmod": "FAN",
+                "fnsp": "0004",
+                "hmod": "HEAT",
+                "hmax": "2982",
+                "ffoc": "ON"
+            }
+        });
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn it_rejects_invalid_commands() {
+        let samples = vec![
+            (StateSetCommand::new(), CommandError::Empty),
+            (StateSetCommand::new().heat_target_kelvin(273.15), CommandError::HeatTargetOutOfRange(273.15)),
+            (StateSetCommand::new().heat_target_kelvin(311.15), CommandError::HeatTargetOutOfRange(311.15)),
+            (
+                StateSetCommand::new().fan_mode(FanMode::Off).fan_speed(FanSpeed::Speed_1),
+                CommandError::ConflictingSettings("fan speed cannot be set while turning the fan off")
+            ),
+            (
+                StateSetCommand::new().fan_mode(FanMode::Fan).fan_speed(FanSpeed::Auto),
+                CommandError::ConflictingSettings("automatic fan speed requires the automatic fan mode")
+            ),
+        ];
+
+        for (command, expected) in samples {
+            let actual = command.validate().unwrap_err();
+
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn it_rejects_settings_the_product_lacks() {
+        let command = StateSetCommand::new().heat_mode(HeatMode::On);
+
+        assert!(command.validate_for(ProductType::PureHotCoolLink).is_ok());
+        assert_eq!(command.validate_for(ProductType::PureCoolLink).unwrap_err(), CommandError::Unsupported {
+            product_type: ProductType::PureCoolLink,
+            setting: "heating"
+        });
+    }
+
+    #[test]
+    fn it_serializes_oscillation_angles() {
+        let command = StateSetCommand::new().oscillation(OscillationStatus::On).oscillation_angles(45, 135);
+
+        let actual = serde_json::to_value(command.data()).unwrap();
+
+        assert_eq!(actual, serde_json::json!({ "oson": "ON", "osal": "0045", "osau": "0135", "ancp": "CUST" }));
+        assert_eq!(command.validate_for(ProductType::PureCoolLink).unwrap_err(), CommandError::Unsupported {
+            product_type: ProductType::PureCoolLink,
+            setting: "settings of the purifier generation"
+        });
+        assert_eq!(StateSetCommand::new().oscillation_angles(45, 60).validate().unwrap_err(),
+                   CommandError::OscillationAnglesOutOfRange(45, 60));
+        assert_eq!(StateSetCommand::new().oscillation_angles(65530, 100).validate().unwrap_err(),
+                   CommandError::OscillationAnglesOutOfRange(65530, 100));
+    }
+
+    #[test]
+    fn it_serializes_humidifier_settings() {
+        let command = StateSetCommand::new()
+            .humidify(HumidifyMode::On)
+            .humidity_target(50)
+            .water_hardness(WaterHardness::Medium);
+
+        let actual = serde_json::to_value(command.data()).unwrap();
+
+        assert_eq!(actual, serde_json::json!({ "hume": "HUMD", "humt": "0050", "wath": "1350" }));
+        assert_eq!(serde_json::to_value(WaterHardness::Soft).unwrap(), serde_json::json!("2025"));
+        assert!(command.validate_for(ProductType::PureHumidifyCool).is_ok());
+        assert_eq!(command.validate_for(ProductType::PureCool).unwrap_err(), CommandError::Unsupported {
+            product_type: ProductType::PureCool,
+            setting: "humidification"
+        });
+        assert_eq!(StateSetCommand::new().humidity_target(80).validate().unwrap_err(),
+                   CommandError::HumidityTargetOutOfRange(80));
+        assert_eq!(serde_json::to_value(StateSetCommand::new().start_deep_clean().data()).unwrap(),
+                   serde_json::json!({ "clcr": "CLAC" }));
+    }
+
+    #[test]
+    fn it_encodes_heat_targets_in_deci_kelvin() {
+        let celsius = StateSetCommand::new().heat_target_celsius(24.85);
+        let fahrenheit = StateSetCommand::new().heat_target_fahrenheit(70.0);
+
+        assert_eq!(serde_json::to_value(celsius.data()).unwrap(), serde_json::json!({ "hmax": "2980" }));
+        assert_eq!(serde_json::to_value(fahrenheit.data()).unwrap(), serde_json::json!({ "hmax": "2943" }));
+
+        assert!(StateSetCommand::new().heat_target_celsius(0.0).validate().is_err());
+        assert!(StateSetCommand::new().heat_target_fahrenheit(100.0).validate().is_err());
+    }
+
+    #[test]
+    fn it_sets_and_cancels_the_sleep_timer() {
+        let set = StateSetCommand::new().sleep_timer(90);
+        let cancel = StateSetCommand::new().cancel_sleep_timer();
+
+        assert_eq!(serde_json::to_value(set.data()).unwrap(), serde_json::json!({ "sltm": "0090" }));
+        assert_eq!(serde_json::to_value(cancel.data()).unwrap(), serde_json::json!({ "sltm": "OFF" }));
+        assert!(StateSetCommand::new().sleep_timer(SLEEP_TIMER_MAX_MINUTES).validate().is_ok());
+        assert_eq!(StateSetCommand::new().sleep_timer(0).validate().unwrap_err(), CommandError::SleepTimerOutOfRange(0));
+        assert_eq!(StateSetCommand::new().sleep_timer(541).validate().unwrap_err(), CommandError::SleepTimerOutOfRange(541));
+
+        let mut confirmation = CommandConfirmation::new(&set);
+        let running = StateSetData { sleep_timer: Some(SleepTimer::Minutes(30)), ..Default::default() };
+        assert!(!confirmation.observe(&running));
+        let observed = StateSetData { sleep_timer: Some(SleepTimer::Minutes(89)), ..Default::default() };
+        assert!(confirmation.observe(&observed));
+
+        assert_eq!(to_sleep_timer(Some(65_600)), SleepTimer::Minutes(u16::MAX));
+        assert_eq!(to_sleep_timer(None), SleepTimer::Off);
+    }
+
+    #[test]
+    fn it_confirms_the_link_sleep_timer_from_sensor_data() {
+        let sensor_data = |sltm: &str| EnvironmentalCurrentSensorDataRaw {
+            tact: String::from("2977"),
+            hact: String::from("0040"),
+            pact: String::from("0001"),
+            vact: String::from("0002"),
+            sltm: String::from(sltm)
+        };
+
+        let mut confirmation = CommandConfirmation::new(&StateSetCommand::new().sleep_timer(90));
+        assert!(!confirmation.observe(&StateSetData::from(&sensor_data("OFF"))));
+        assert!(confirmation.observe(&StateSetData::from(&sensor_data("0089"))));
+
+        let mut confirmation = CommandConfirmation::new(&StateSetCommand::new().cancel_sleep_timer());
+        assert!(confirmation.observe(&StateSetData::from(&sensor_data("OFF"))));
+    }
+
+    #[test]
+    fn it_accepts_heat_target_range_bounds() {
+        for kelvin in [HEAT_TARGET_MIN_KELVIN, HEAT_TARGET_MAX_KELVIN].iter() {
+            let command = StateSetCommand::new().heat_target_kelvin(*kelvin);
+
+            assert_eq!(command.validate(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn it_confirms_requested_fields_from_reported_state() {
+        let command = StateSetCommand::new()
+            .fan_mode(FanMode::Fan)
+            .fan_speed(FanSpeed::Speed_4)
+            .heat_target_kelvin(298.15);
+
+        let mut confirmation = CommandConfirmation::new(&command);
+        assert_eq!(confirmation.acknowledgement(), None);
+
+        let change = ProductStateChange {
+            fan_mode: Some(ValueChange { old: FanMode::Auto, new: FanMode::Fan }),
+            fan_speed: Some(ValueChange { old: FanSpeed::Auto, new: FanSpeed::Speed_2 }),
+            ..Default::default()
+        };
+        assert!(!confirmation.observe(&StateSetData::from(&change)));
+
+        let change = ProductStateChange {
+            heat_target_kelvin: Some(ValueChange { old: 296.2, new: 298.2 }),
+            ..Default::default()
+        };
+        assert!(!confirmation.observe(&StateSetData::from(&change)));
+
+        let expected = CommandAcknowledgement {
+            confirmed: vec![ProductStateField::FanMode, ProductStateField::HeatTarget],
+            ignored: vec![ProductStateField::FanSpeed]
+        };
+        assert_eq!(confirmation.acknowledgement(), Some(expected));
+    }
+
+    #[test]
+    fn it_ignores_states_without_requested_fields() {
+        let mut confirmation = CommandConfirmation::new(&StateSetCommand::new().night_mode(NightMode::On));
+
+        let unrelated = StateSetData { sleep_timer: Some(SleepTimer::Off), ..Default::default() };
+        assert!(!confirmation.observe(&unrelated));
+        assert_eq!(confirmation.acknowledgement(), None);
+
+        let stale = StateSetData { night_mode: Some(NightMode::Off), ..Default::default() };
+        assert!(!confirmation.observe(&stale));
+        assert_eq!(confirmation.acknowledgement().unwrap().ignored, vec![ProductStateField::NightMode]);
+    }
+
+    #[test]
+    fn it_completes_once_every_field_is_confirmed() {
+        let command = StateSetCommand::new()
+            .night_mode(NightMode::On)
+            .oscillation(OscillationStatus::Off);
+
+        let mut confirmation = CommandConfirmation::new(&command);
+
+        let observed = StateSetData {
+            night_mode: Some(NightMode::On),
+            oscillation_status: Some(OscillationStatus::Off),
+            fan_mode: Some(FanMode::Auto),
+            ..Default::default()
+        };
+
+        assert!(confirmation.observe(&observed));
+        assert_eq!(confirmation.acknowledgement().unwrap().ignored, vec![]);
+    }
+}
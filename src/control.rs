@@ -1,4 +1,4 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "UPPERCASE")]
@@ -77,7 +77,7 @@ pub enum NightMode {
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum FanFocusMode {
-    #[serde(rename = "On")]
+    #[serde(rename = "ON")]
     Focus,
     #[serde(rename = "OFF")]
     Wide,
@@ -106,3 +106,101 @@ pub enum TiltState {
     #[serde(rename = "OK")]
     No
 }
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum FanPower {
+    On,
+    Off
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum AutoMode {
+    On,
+    Off
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum OscillationState {
+    On,
+    Off,
+    Idle
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum FrontAirflow {
+    On,
+    Off
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum HumidifyMode {
+    Off,
+    #[serde(rename = "HUMD")]
+    On
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum AutoHumidity {
+    On,
+    Off
+}
+
+/// Sent as the descaling interval in hours, so softer water means a longer interval.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum WaterHardness {
+    #[serde(rename = "2025")]
+    Soft,
+    #[serde(rename = "1350")]
+    Medium,
+    #[serde(rename = "0675")]
+    Hard
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CleanCycle {
+    #[serde(rename = "CLNO")]
+    Idle,
+    #[serde(rename = "CLAC")]
+    Active,
+    #[serde(rename = "CLCM")]
+    Complete
+}
+
+/// Sleep timer as sent in `sltm`: `OFF` or the minutes left, e.g. "0060".
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SleepTimer {
+    Off,
+    Minutes(u16)
+}
+
+impl SleepTimer {
+    pub fn is_on(self) -> bool {
+        self != SleepTimer::Off
+    }
+}
+
+impl Serialize for SleepTimer {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            SleepTimer::Off => serializer.serialize_str("OFF"),
+            SleepTimer::Minutes(minutes) => serializer.serialize_str(&format!("{:04}", minutes))
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for SleepTimer {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+
+        match value.as_str() {
+            "OFF" => Ok(SleepTimer::Off),
+            minutes => minutes.parse().map(SleepTimer::Minutes).map_err(serde::de::Error::custom)
+        }
+    }
+}
@@ -0,0 +1,714 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use futures::StreamExt;
+use serde::Serialize;
+use tokio::sync::{broadcast, oneshot};
+
+use super::api_error::*;
+use super::command::*;
+use super::discovery::{discover, DYSON_360_EYE_NAME_PREFIX, DYSON_360_EYE_PRODUCT_TYPE};
+use super::model::*;
+use super::mqtt::*;
+use super::product::*;
+use super::state::*;
+use super::util::*;
+
+const MQTT_PORT: u16 = 1883;
+const DYSON_WIFI_SSID_PREFIX: &str = "DYSON-";
+const MQTT_QOS: i32 = 1;
+const MESSAGE_BUFFER_SIZE: usize = 64;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeviceMessage {
+    StatusCurrent(StatusCurrentResponse),
+    PurifierStatusCurrent(PurifierStatusCurrentResponse),
+    StatusConnection(StatusConnectionResponse),
+    Unrecognized {
+        topic: String,
+        payload: String
+    }
+}
+
+impl DeviceMessage {
+    pub fn from_mqtt(topic: &str, payload: &[u8]) -> Self {
+        let generation = topic.split('/').next()
+            .and_then(ProductType::from_code)
+            .map(ProductType::generation);
+
+        let parsed = if topic.ends_with("/status/current") {
+            let link = || serde_json::from_slice(payload).map(DeviceMessage::StatusCurrent).ok();
+            let purifier = || serde_json::from_slice(payload).map(DeviceMessage::PurifierStatusCurrent).ok();
+
+            match generation {
+                Some(ProductGeneration::Purifier) => purifier(),
+                Some(_) => link(),
+                None => link().or_else(purifier)
+            }
+        } else if topic.ends_with("/status/connection") {
+            serde_json::from_slice(payload).map(DeviceMessage::StatusConnection).ok()
+        } else {
+            None
+        };
+
+        parsed.unwrap_or_else(|| DeviceMessage::Unrecognized {
+            topic: String::from(topic),
+            payload: String::from_utf8_lossy(payload).into_owned()
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LocalDeviceOptions {
+    /// MQTT port of the device; discovery reports it when a device doesn't use the default.
+    pub port: u16,
+    pub request_timeout: Duration,
+    pub command_timeout: Duration,
+    pub keep_alive: Duration,
+    pub reconnect_min_delay: Duration,
+    pub reconnect_max_delay: Duration
+}
+
+impl Default for LocalDeviceOptions {
+    fn default() -> Self {
+        LocalDeviceOptions {
+            port: MQTT_PORT,
+            request_timeout: Duration::from_secs(5),
+            command_timeout: Duration::from_secs(10),
+            keep_alive: Duration::from_secs(30),
+            reconnect_min_delay: Duration::from_secs(1),
+            reconnect_max_delay: Duration::from_secs(60)
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConnectionEvent {
+    Connected,
+    Disconnected,
+    Reconnecting {
+        attempt: u32,
+        delay: Duration
+    },
+    DeviceOnline,
+    DeviceOffline
+}
+
+/// Everything needed to reach a device's local broker, whether it came from the
+/// cloud manifest or from the Wi-Fi details printed on the device label.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LocalConnectionInfo {
+    pub serial: String,
+    pub product_type: String,
+    pub credentials: LocalMqttCredentials
+}
+
+impl LocalConnectionInfo {
+    pub fn from_manifest(device_manifest: &DeviceManifest) -> Result<Self, DysonError> {
+        Ok(LocalConnectionInfo {
+            serial: device_manifest.serial.clone(),
+            product_type: device_manifest.product_type.clone(),
+            credentials: device_manifest.local_mqtt_credentials()?
+        })
+    }
+
+    /// Accepts product SSIDs like `DYSON-ABC-DE-FGH1234A-455` and `360EYE-ABC-DE-FGH1234A`.
+    pub fn from_wifi(ssid: &str, wifi_password: &str) -> Result<Self, DysonError> {
+        let (serial, product_type) = parse_wifi_ssid(ssid)
+            .ok_or_else(|| DysonError::InvalidWifiSsid(String::from(ssid)))?;
+
+        Ok(LocalConnectionInfo {
+            credentials: LocalMqttCredentials {
+                username: serial.clone(),
+                password: sha512_base64(wifi_password)
+            },
+            serial,
+            product_type
+        })
+    }
+
+    pub fn product(&self) -> Option<ProductType> {
+        ProductType::from_code(&self.product_type)
+    }
+}
+
+fn parse_wifi_ssid(ssid: &str) -> Option<(String, String)> {
+    let ssid = ssid.trim();
+
+    if let Some(serial) = ssid.strip_prefix(DYSON_360_EYE_NAME_PREFIX) {
+        return Some((String::from(serial), String::from(DYSON_360_EYE_PRODUCT_TYPE)))
+            .filter(|(serial, _)| !serial.is_empty());
+    }
+
+    let (serial, product_type) = ssid.strip_prefix(DYSON_WIFI_SSID_PREFIX)?.rsplit_once('-')?;
+
+    if serial.is_empty() || product_type.is_empty() {
+        return None;
+    }
+
+    Some((String::from(serial), String::from(product_type)))
+}
+
+pub struct LocalDevice {
+    pub serial: String,
+    pub product_type: String,
+    pub options: LocalDeviceOptions,
+    client: paho_mqtt::AsyncClient,
+    sender: broadcast::Sender<DeviceMessage>,
+    events: broadcast::Sender<ConnectionEvent>,
+    closing: Arc<AtomicBool>,
+    shutdown: Mutex<Option<oneshot::Sender<()>>>
+}
+
+impl LocalDevice {
+    pub async fn connect(device_manifest: &DeviceManifest, host: &str) -> Result<Self, DysonError> {
+        Self::connect_with_options(device_manifest, host, Default::default()).await
+    }
+
+    pub async fn connect_with_options(device_manifest: &DeviceManifest,
+                                      host: &str,
+                                      options: LocalDeviceOptions) -> Result<Self, DysonError> {
+        let connection_info = LocalConnectionInfo::from_manifest(device_manifest)?;
+
+        Self::connect_with_info(&connection_info, host, options).await
+    }
+
+    /// Finds the device described by the manifest on the local network and connects to it.
+    pub async fn discover_and_connect(device_manifest: &DeviceManifest,
+                                      timeout: Duration) -> Result<Self, DysonError> {
+        let devices = discover(timeout).await?;
+        let device = devices.iter()
+            .find(|device| device.find_manifest(std::slice::from_ref(device_manifest)).is_some())
+            .ok_or_else(|| DysonError::DeviceNotFound(device_manifest.serial.clone()))?;
+
+        let options = LocalDeviceOptions { port: device.port, ..Default::default() };
+
+        LocalDevice::connect_with_options(device_manifest, &device.host(), options).await
+    }
+
+    /// Connects without the cloud, e.g. with `LocalConnectionInfo::from_wifi`.
+    pub async fn connect_with_info(connection_info: &LocalConnectionInfo,
+                                   host: &str,
+                                   options: LocalDeviceOptions) -> Result<Self, DysonError> {
+        let credentials = &connection_info.credentials;
+
+        let create_options = paho_mqtt::CreateOptionsBuilder::new()
+            .server_uri(format!("tcp://{}:{}", host, options.port))
+            .client_id(format!("dyson-rs-{}-{}", connection_info.serial, chrono::Utc::now().timestamp_millis()))
+            .finalize();
+
+        let connect_options = paho_mqtt::ConnectOptionsBuilder::new()
+            .user_name(credentials.username.as_str())
+            .password(credentials.password.as_str())
+            .keep_alive_interval(options.keep_alive)
+            .clean_session(true)
+            .finalize();
+
+        let mut client = paho_mqtt::AsyncClient::new(create_options)?;
+        let stream = client.get_stream(MESSAGE_BUFFER_SIZE);
+        let (sender, _) = broadcast::channel(MESSAGE_BUFFER_SIZE);
+        let (events, _) = broadcast::channel(MESSAGE_BUFFER_SIZE);
+        let (shutdown, stopped) = oneshot::channel();
+
+        let device = LocalDevice {
+            serial: connection_info.serial.clone(),
+            product_type: connection_info.product_type.clone(),
+            options,
+            client,
+            sender,
+            events,
+            closing: Arc::new(AtomicBool::new(false)),
+            shutdown: Mutex::new(Some(shutdown))
+        };
+
+        device.client.connect(connect_options).await?;
+        subscribe(&device.client, &device.status_topics()).await?;
+
+        let connection = Connection {
+            client: device.client.clone(),
+            topics: device.status_topics(),
+            command_topic: device.topic("command"),
+            options: device.options.clone(),
+            messages: device.sender.clone(),
+            events: device.events.clone(),
+            closing: device.closing.clone()
+        };
+        tokio::spawn(connection.run(stream, stopped));
+
+        Ok(device)
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<DeviceMessage> {
+        self.sender.subscribe()
+    }
+
+    pub fn connection_events(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.events.subscribe()
+    }
+
+    /// Sends the command and waits until the device reports a state reflecting it.
+    ///
+    /// Fails if the device reports none of the requested fields within `options.command_timeout`.
+    pub async fn send_command(&self, command: &StateSetCommand) -> Result<CommandAcknowledgement, DysonError> {
+        if let Some(product_type) = self.product() {
+            command.validate_for(product_type)?;
+        }
+
+        let payload = command.to_payload()?;
+        let mut receiver = self.subscribe();
+        let mut confirmation = CommandConfirmation::new(command);
+
+        self.publish(&payload).await?;
+
+        if command.data().sleep_timer.is_some() {
+            self.publish(&RequestPayload::new(REQUEST_ENVIRONMENT_CURRENT_SENSOR_DATA)).await?;
+        }
+
+        // reaching the timeout is not an error as long as a requested field has been reported
+        let _ = tokio::time::timeout(self.options.command_timeout, async {
+            loop {
+                match receiver.recv().await {
+                    Ok(message) => {
+                        if let Some(observed) = observed_state(&message) {
+                            if confirmation.observe(&observed) {
+                                break;
+                            }
+                        }
+                    },
+                    Err(broadcast::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::RecvError::Closed) => break
+                }
+            }
+        }).await;
+
+        confirmation.acknowledgement()
+            .ok_or(DysonError::Timeout)
+    }
+
+    /// Works for both the Link and the purifier generation.
+    pub async fn request_current_state(&self) -> Result<DeviceState, DysonError> {
+        self.request(REQUEST_CURRENT_STATE, reported_state).await
+    }
+
+    /// Works for both the Link and the purifier generation.
+    pub async fn request_environment_data(&self) -> Result<SensorReading, DysonError> {
+        self.request(REQUEST_ENVIRONMENT_CURRENT_SENSOR_DATA, reported_sensor_reading).await
+    }
+
+    /// Periodically requests environment sensor data until the returned poller is dropped.
+    pub fn start_polling(&self, period: Duration) -> Poller {
+        let (stop, mut stopped) = oneshot::channel();
+        let client = self.client.clone();
+        let topic = self.topic("command");
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        // a missed poll is recovered by the next tick
+                        let _ = publish(&client, &topic, &RequestPayload::new(REQUEST_ENVIRONMENT_CURRENT_SENSOR_DATA)).await;
+                    },
+                    _ = &mut stopped => break
+                }
+            }
+        });
+
+        Poller { stop: Some(stop) }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.client.is_connected()
+    }
+
+    pub async fn disconnect(&self) -> Result<(), DysonError> {
+        self.stop_connection();
+        self.client.disconnect(None).await?;
+        Ok(())
+    }
+
+    pub fn topic(&self, suffix: &str) -> String {
+        format!("{}/{}/{}", self.product_type, self.serial, suffix)
+    }
+
+    /// Ends the background task, which otherwise keeps its client alive after a voluntary disconnect.
+    fn stop_connection(&self) {
+        self.closing.store(true, Ordering::SeqCst);
+
+        if let Some(shutdown) = self.shutdown.lock().unwrap().take() {
+            let _ = shutdown.send(());
+        }
+    }
+
+    fn status_topics(&self) -> Vec<String> {
+        vec![
+            self.topic("status/current"),
+            self.topic("status/connection")
+        ]
+    }
+
+    async fn publish<T: Serialize>(&self, payload: &T) -> Result<(), DysonError> {
+        publish(&self.client, &self.topic("command"), payload).await?;
+        Ok(())
+    }
+
+    async fn request<T, F>(&self, message: &str, matcher: F) -> Result<T, DysonError>
+        where F: Fn(DeviceMessage) -> Option<T>
+    {
+        let mut receiver = self.subscribe();
+
+        self.publish(&RequestPayload::new(message)).await?;
+        wait_for(&mut receiver, self.options.request_timeout, matcher).await
+    }
+
+    pub fn product(&self) -> Option<ProductType> {
+        ProductType::from_code(&self.product_type)
+    }
+}
+
+impl Drop for LocalDevice {
+    fn drop(&mut self) {
+        self.stop_connection();
+    }
+}
+
+pub struct Poller {
+    stop: Option<oneshot::Sender<()>>
+}
+
+impl Poller {
+    pub fn stop(self) {}
+}
+
+impl Drop for Poller {
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+    }
+}
+
+fn reported_state(message: DeviceMessage) -> Option<DeviceState> {
+    match message {
+        DeviceMessage::StatusCurrent(StatusCurrentResponse::CurrentState(current_state)) =>
+            Some(DeviceState::from(&current_state.product_state)),
+        DeviceMessage::PurifierStatusCurrent(PurifierStatusCurrentResponse::CurrentState(current_state)) =>
+            Some(DeviceState::from(&current_state.product_state)),
+        _ => None
+    }
+}
+
+fn reported_sensor_reading(message: DeviceMessage) -> Option<SensorReading> {
+    match message {
+        DeviceMessage::StatusCurrent(StatusCurrentResponse::EnvironmentalCurrentSensorData(sensor_data)) =>
+            Some(SensorReading::from(&sensor_data.data)),
+        DeviceMessage::PurifierStatusCurrent(PurifierStatusCurrentResponse::EnvironmentalCurrentSensorData(sensor_data)) =>
+            Some(SensorReading::from(&PurifierEnvironmentCurrentSensorData::from_raw(&sensor_data.data))),
+        _ => None
+    }
+}
+
+fn observed_state(message: &DeviceMessage) -> Option<StateSetData> {
+    match message {
+        DeviceMessage::StatusCurrent(StatusCurrentResponse::CurrentState(current_state)) =>
+            Some(StateSetData::from(&current_state.product_state)),
+        DeviceMessage::StatusCurrent(StatusCurrentResponse::StateChange(state_change)) =>
+            Some(StateSetData::from(&state_change.product_state)),
+        DeviceMessage::StatusCurrent(StatusCurrentResponse::EnvironmentalCurrentSensorData(sensor_data)) =>
+            Some(StateSetData::from(&sensor_data.data)),
+        DeviceMessage::PurifierStatusCurrent(PurifierStatusCurrentResponse::CurrentState(current_state)) =>
+            Some(StateSetData::from(&current_state.product_state)),
+        DeviceMessage::PurifierStatusCurrent(PurifierStatusCurrentResponse::StateChange(state_change)) =>
+            Some(StateSetData::from(&state_change.product_state)),
+        _ => None
+    }
+}
+
+async fn publish<T: Serialize>(client: &paho_mqtt::AsyncClient, topic: &str, payload: &T) -> Result<(), DysonError> {
+    let payload = serde_json::to_string(payload).map_err(DysonError::Serialize)?;
+    let message = paho_mqtt::Message::new(topic, payload, MQTT_QOS);
+
+    client.publish(message).await?;
+    Ok(())
+}
+
+async fn wait_for<T, F>(receiver: &mut broadcast::Receiver<DeviceMessage>,
+                        timeout: Duration,
+                        matcher: F) -> Result<T, DysonError>
+    where F: Fn(DeviceMessage) -> Option<T>
+{
+    let response = tokio::time::timeout(timeout, async {
+        loop {
+            match receiver.recv().await {
+                Ok(message) => {
+                    if let Some(response) = matcher(message) {
+                        return Ok(response);
+                    }
+                },
+                Err(broadcast::RecvError::Lagged(_)) => continue,
+                Err(broadcast::RecvError::Closed) => return Err(DysonError::ConnectionClosed)
+            }
+        }
+    }).await??;
+
+    Ok(response)
+}
+
+/// Background side of a `LocalDevice`: dispatches incoming messages and restores the
+/// connection whenever the broker drops it.
+struct Connection {
+    client: paho_mqtt::AsyncClient,
+    topics: Vec<String>,
+    command_topic: String,
+    options: LocalDeviceOptions,
+    messages: broadcast::Sender<DeviceMessage>,
+    events: broadcast::Sender<ConnectionEvent>,
+    closing: Arc<AtomicBool>
+}
+
+impl Connection {
+    async fn run(self,
+                 mut stream: futures::channel::mpsc::Receiver<Option<paho_mqtt::Message>>,
+                 mut stopped: oneshot::Receiver<()>) {
+        loop {
+            let message = tokio::select! {
+                message = stream.next() => message,
+                _ = &mut stopped => break
+            };
+
+            match message {
+                Some(Some(message)) => self.dispatch(DeviceMessage::from_mqtt(message.topic(), message.payload())),
+                Some(None) if self.closing.load(Ordering::SeqCst) => break,
+                Some(None) => {
+                    self.emit(ConnectionEvent::Disconnected);
+
+                    tokio::select! {
+                        _ = self.reconnect() => {},
+                        _ = &mut stopped => break
+                    }
+                },
+                None => break
+            }
+        }
+    }
+
+    fn dispatch(&self, message: DeviceMessage) {
+        if let DeviceMessage::StatusConnection(connection) = &message {
+            self.emit(if connection.is_online() {
+                ConnectionEvent::DeviceOnline
+            } else {
+                ConnectionEvent::DeviceOffline
+            });
+        }
+
+        // an error only means nobody is subscribed at the moment
+        let _ = self.messages.send(message);
+    }
+
+    async fn reconnect(&self) {
+        let mut attempt = 0;
+
+        while !self.closing.load(Ordering::SeqCst) {
+            attempt += 1;
+            let delay = reconnect_delay(attempt, self.options.reconnect_min_delay, self.options.reconnect_max_delay);
+
+            self.emit(ConnectionEvent::Reconnecting { attempt, delay });
+            tokio::time::delay_for(delay).await;
+
+            if self.client.reconnect().await.is_ok() && subscribe(&self.client, &self.topics).await.is_ok() {
+                // the state may have changed while we were away
+                let _ = publish(&self.client, &self.command_topic, &RequestPayload::new(REQUEST_CURRENT_STATE)).await;
+
+                self.emit(ConnectionEvent::Connected);
+                return;
+            }
+        }
+    }
+
+    fn emit(&self, event: ConnectionEvent) {
+        let _ = self.events.send(event);
+    }
+}
+
+fn reconnect_delay(attempt: u32, min_delay: Duration, max_delay: Duration) -> Duration {
+    let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+
+    min_delay.checked_mul(factor)
+        .map_or(max_delay, |delay| delay.min(max_delay))
+}
+
+async fn subscribe(client: &paho_mqtt::AsyncClient, topics: &[String]) -> Result<(), paho_mqtt::Error> {
+    let qos = vec![MQTT_QOS; topics.len()];
+
+    client.subscribe_many(topics, &qos).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::control::FanSpeed;
+
+    #[test]
+    fn it_derives_connection_info_from_wifi() {
+        let actual = LocalConnectionInfo::from_wifi("DYSON-ABC-DE-FGH1234A-438M", "abcdefgh").unwrap();
+
+        assert_eq!(actual.serial, "ABC-DE-FGH1234A");
+        assert_eq!(actual.product_type, "438M");
+        assert_eq!(actual.credentials.username, "ABC-DE-FGH1234A");
+        assert_eq!(actual.credentials.password, sha512_base64("abcdefgh"));
+
+        let actual = LocalConnectionInfo::from_wifi("360EYE-ABC-DE-FGH1234A", "abcdefgh").unwrap();
+        assert_eq!(actual.product_type, "N223");
+
+        assert!(matches!(LocalConnectionInfo::from_wifi("HomeNetwork", "abcdefgh"), Err(DysonError::InvalidWifiSsid(_))));
+    }
+
+    #[test]
+    fn it_parses_message_by_topic() {
+        let payload = br#"
+            {
+                "msg":"ENVIRONMENTAL-CURRENT-SENSOR-DATA",
+                "time":"2020-06-09T14:05:04.000Z",
+                "data": {
+                    "tact":"2980",
+                    "hact":"0056",
+                    "pact":"0003",
+                    "vact":"0002",
+                    "sltm":"OFF"
+                }
+            }"#;
+
+        let actual = DeviceMessage::from_mqtt("455/ABC-DE-FGH1234A/status/current", payload);
+
+        let expected = DeviceMessage::StatusCurrent(StatusCurrentResponse::EnvironmentalCurrentSensorData(EnvironmentalCurrentSensorRaw{
+            time: String::from("2020-06-09T14:05:04.000Z"),
+            data: EnvironmentalCurrentSensorDataRaw{
+                tact: String::from("2980"),
+                hact: String::from("0056"),
+                pact: String::from("0003"),
+                vact: String::from("0002"),
+                sltm: String::from("OFF"),
+            },
+        }));
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn it_keeps_unrecognized_messages() {
+        let samples = vec![
+            ("455/ABC-DE-FGH1234A/status/current", r#"{"msg":"SOMETHING-NEW"}"#),
+            ("455/ABC-DE-FGH1234A/status/faults", r#"{"msg":"CURRENT-FAULTS"}"#),
+        ];
+
+        for (topic, payload) in samples {
+            let actual = DeviceMessage::from_mqtt(topic, payload.as_bytes());
+
+            let expected = DeviceMessage::Unrecognized {
+                topic: String::from(topic),
+                payload: String::from(payload)
+            };
+
+            assert_eq!(actual, expected);
+        }
+    }
+
+    fn sensor_data_message() -> DeviceMessage {
+        DeviceMessage::from_mqtt("455/ABC-DE-FGH1234A/status/current", br#"
+            {
+                "msg":"ENVIRONMENTAL-CURRENT-SENSOR-DATA",
+                "time":"2020-06-09T14:05:04.000Z",
+                "data": {
+                    "tact":"2980",
+                    "hact":"0056",
+                    "pact":"0003",
+                    "vact":"0002",
+                    "sltm":"OFF"
+                }
+            }"#)
+    }
+
+    #[tokio::test]
+    async fn it_waits_for_matching_message() {
+        let (sender, mut receiver) = broadcast::channel(4);
+
+        sender.send(DeviceMessage::from_mqtt("455/ABC-DE-FGH1234A/status/current", b"{}")).unwrap();
+        sender.send(sensor_data_message()).unwrap();
+
+        let actual = wait_for(&mut receiver, Duration::from_secs(1), |message| match message {
+            DeviceMessage::StatusCurrent(StatusCurrentResponse::EnvironmentalCurrentSensorData(sensor_data)) => Some(sensor_data.time),
+            _ => None
+        }).await.unwrap();
+
+        assert_eq!(actual, "2020-06-09T14:05:04.000Z");
+    }
+
+    #[tokio::test]
+    async fn it_waits_for_purifier_generation_replies() {
+        let (sender, mut receiver) = broadcast::channel(4);
+
+        sender.send(DeviceMessage::from_mqtt("438/ABC-DE-FGH1234A/status/current", br#"
+            {
+                "msg":"ENVIRONMENTAL-CURRENT-SENSOR-DATA",
+                "time":"2021-03-08T10:00:00.000Z",
+                "data": { "tact":"2977", "hact":"0040", "pm25":"0012", "pm10":"0008", "va10":"INIT", "sltm":"OFF" }
+            }"#)).unwrap();
+        sender.send(DeviceMessage::from_mqtt("438/ABC-DE-FGH1234A/status/current", br#"
+            {
+                "msg":"CURRENT-STATE",
+                "time":"2021-03-08T10:00:00.000Z",
+                "mode-reason":"LAPP",
+                "state-reason":"MODE",
+                "rssi":"-46",
+                "product-state": {
+                    "fpwr":"ON", "auto":"OFF", "oscs":"OFF", "oson":"OFF", "nmod":"OFF", "rhtm":"ON",
+                    "fnst":"FAN", "ercd":"NONE", "wacd":"NONE", "fnsp":"0004"
+                }
+            }"#)).unwrap();
+
+        let sensor_reading = wait_for(&mut receiver, Duration::from_secs(1), reported_sensor_reading).await.unwrap();
+        assert_eq!(sensor_reading.pm25, Some(12.0));
+
+        let state = wait_for(&mut receiver, Duration::from_secs(1), reported_state).await.unwrap();
+        assert!(state.power);
+        assert_eq!(state.fan_speed, FanSpeed::Speed_4);
+    }
+
+    #[tokio::test]
+    async fn it_times_out_without_matching_message() {
+        let (sender, mut receiver) = broadcast::channel(4);
+
+        sender.send(sensor_data_message()).unwrap();
+
+        let actual = wait_for(&mut receiver, Duration::from_millis(10), |message| match message {
+            DeviceMessage::StatusCurrent(StatusCurrentResponse::CurrentState(current_state)) => Some(current_state),
+            _ => None
+        }).await;
+
+        assert!(matches!(actual, Err(DysonError::Timeout)));
+    }
+
+    #[test]
+    fn it_backs_off_exponentially_up_to_max_delay() {
+        let min_delay = Duration::from_secs(1);
+        let max_delay = Duration::from_secs(60);
+
+        let samples = vec![
+            (1, 1),
+            (2, 2),
+            (3, 4),
+            (6, 32),
+            (7, 60),
+            (100, 60),
+        ];
+
+        for (attempt, expected) in samples {
+            let actual = reconnect_delay(attempt, min_delay, max_delay);
+
+            assert_eq!(actual, Duration::from_secs(expected));
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use super::api_error::*;
+use super::model::*;
+
+pub const DYSON_SERVICE_TYPE: &str = "_dyson_mqtt._tcp.local.";
+pub const DYSON_360_EYE_SERVICE_TYPE: &str = "_360eye_mqtt._tcp.local.";
+
+pub(crate) const DYSON_360_EYE_PRODUCT_TYPE: &str = "N223";
+pub(crate) const DYSON_360_EYE_NAME_PREFIX: &str = "360EYE-";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DiscoveredDevice {
+    pub serial: String,
+    pub product_type: String,
+    pub address: IpAddr,
+    pub port: u16
+}
+
+impl DiscoveredDevice {
+    pub fn host(&self) -> String {
+        self.address.to_string()
+    }
+
+    pub fn find_manifest<'a>(&self, device_manifests: &'a [DeviceManifest]) -> Option<&'a DeviceManifest> {
+        device_manifests.iter()
+            .find(|device_manifest| device_manifest.serial.eq_ignore_ascii_case(&self.serial))
+    }
+}
+
+/// Browses the local network for Dyson devices until the timeout elapses.
+pub async fn discover(timeout: Duration) -> Result<Vec<DiscoveredDevice>, DysonError> {
+    let daemon = mdns_sd::ServiceDaemon::new()?;
+    let deadline = Instant::now() + timeout;
+
+    let browsers = vec![DYSON_SERVICE_TYPE, DYSON_360_EYE_SERVICE_TYPE].into_iter()
+        .map(|service_type| {
+            let daemon = daemon.clone();
+            tokio::task::spawn_blocking(move || browse(&daemon, service_type, deadline))
+        })
+        .collect::<Vec<_>>();
+
+    let mut devices: Vec<DiscoveredDevice> = vec![];
+    for browser in browsers {
+        let found = browser.await
+            .map_err(|err| mdns_sd::Error::Msg(err.to_string()))??;
+
+        for device in found {
+            if !devices.contains(&device) {
+                devices.push(device);
+            }
+        }
+    }
+
+    // the daemon thread winds down on its own once the browsers are gone
+    let _ = daemon.shutdown();
+    Ok(devices)
+}
+
+/// Discovers devices and pairs them with the manifest entries returned by the cloud.
+pub async fn discover_manifest_devices(device_manifests: &[DeviceManifest],
+                                       timeout: Duration) -> Result<Vec<(DeviceManifest, DiscoveredDevice)>, DysonError> {
+    let devices = discover(timeout).await?;
+
+    Ok(match_manifests(&devices, device_manifests))
+}
+
+pub fn match_manifests(devices: &[DiscoveredDevice], device_manifests: &[DeviceManifest]) -> Vec<(DeviceManifest, DiscoveredDevice)> {
+    devices.iter()
+        .filter_map(|device| device.find_manifest(device_manifests)
+            .map(|device_manifest| (device_manifest.clone(), device.clone())))
+        .collect()
+}
+
+/// Splits a service instance name into its serial and product type.
+pub fn parse_service_name(fullname: &str, service_type: &str) -> Option<(String, String)> {
+    let instance = fullname.strip_suffix(service_type)?.strip_suffix('.')?;
+
+    if service_type == DYSON_360_EYE_SERVICE_TYPE {
+        let serial = instance.strip_prefix(DYSON_360_EYE_NAME_PREFIX).unwrap_or(instance);
+        return Some((String::from(serial), String::from(DYSON_360_EYE_PRODUCT_TYPE)));
+    }
+
+    let separator = instance.find('_')?;
+    let (product_type, serial) = (&instance[..separator], &instance[separator + 1..]);
+
+    if product_type.is_empty() || serial.is_empty() {
+        return None;
+    }
+
+    Some((String::from(serial), String::from(product_type)))
+}
+
+fn browse(daemon: &mdns_sd::ServiceDaemon, service_type: &str, deadline: Instant) -> Result<Vec<DiscoveredDevice>, mdns_sd::Error> {
+    let receiver = daemon.browse(service_type)?;
+    let mut devices = vec![];
+
+    while let Ok(event) = receiver.recv_deadline(deadline) {
+        if let mdns_sd::ServiceEvent::ServiceResolved(info) = event {
+            let parsed = parse_service_name(info.get_fullname(), service_type);
+            let address = info.get_addresses().iter().find(|address| address.is_ipv4())
+                .or_else(|| info.get_addresses().iter().next());
+
+            if let (Some((serial, product_type)), Some(address)) = (parsed, address) {
+                devices.push(DiscoveredDevice {
+                    serial,
+                    product_type,
+                    address: *address,
+                    port: info.get_port()
+                });
+            }
+        }
+    }
+
+    let _ = daemon.stop_browse(service_type);
+    Ok(devices)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn device_manifest(serial: &str) -> DeviceManifest {
+        DeviceManifest {
+            serial: String::from(serial),
+            name: String::from("Living room"),
+            version: String::from("21.03.08"),
+            local_credentials: String::new(),
+            auto_update: true,
+            new_version_available: false,
+            product_type: String::from("455"),
+            connection_type: String::from("wss"),
+            connected_configuration: None
+        }
+    }
+
+    #[test]
+    fn it_parses_service_names() {
+        let samples = vec![
+            ("455_ABC-DE-FGH1234A._dyson_mqtt._tcp.local.", DYSON_SERVICE_TYPE, Some(("ABC-DE-FGH1234A", "455"))),
+            ("438M_ABC-DE-FGH1234A._dyson_mqtt._tcp.local.", DYSON_SERVICE_TYPE, Some(("ABC-DE-FGH1234A", "438M"))),
+            ("360EYE-ABC-DE-FGH1234A._360eye_mqtt._tcp.local.", DYSON_360_EYE_SERVICE_TYPE, Some(("ABC-DE-FGH1234A", "N223"))),
+            ("ABC-DE-FGH1234A._dyson_mqtt._tcp.local.", DYSON_SERVICE_TYPE, None),
+            ("455_ABC-DE-FGH1234A._http._tcp.local.", DYSON_SERVICE_TYPE, None),
+        ];
+
+        for (fullname, service_type, expected) in samples {
+            let actual = parse_service_name(fullname, service_type);
+            let expected = expected.map(|(serial, product_type)| (String::from(serial), String::from(product_type)));
+
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn it_matches_discovered_devices_to_manifest() {
+        let devices = vec![
+            DiscoveredDevice {
+                serial: String::from("ABC-DE-FGH1234A"),
+                product_type: String::from("455"),
+                address: "192.168.1.20".parse().unwrap(),
+                port: 1883
+            },
+            DiscoveredDevice {
+                serial: String::from("XYZ-DE-FGH9999Z"),
+                product_type: String::from("438"),
+                address: "192.168.1.21".parse().unwrap(),
+                port: 1883
+            },
+        ];
+        let device_manifests = vec![device_manifest("abc-de-fgh1234a"), device_manifest("NOT-ON-LAN0000")];
+
+        let actual = match_manifests(&devices, &device_manifests);
+
+        assert_eq!(actual.len(), 1);
+        assert_eq!(actual[0].0.serial, "abc-de-fgh1234a");
+        assert_eq!(actual[0].1.host(), "192.168.1.20");
+    }
+}
@@ -0,0 +1,394 @@
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, TimeZone, Utc};
+
+use super::model::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HistoryResolution {
+    Daily,
+    Weekly
+}
+
+impl HistoryResolution {
+    /// Time covered by one period of samples.
+    pub fn period_length(self) -> Duration {
+        match self {
+            HistoryResolution::Daily => Duration::days(1),
+            HistoryResolution::Weekly => Duration::weeks(1)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aggregation {
+    Min,
+    Max,
+    Mean
+}
+
+impl Aggregation {
+    fn apply<I: Iterator<Item = f32>>(self, values: I) -> Option<f32> {
+        let mut count = 0;
+        let mut result: Option<f32> = None;
+
+        for value in values {
+            count += 1;
+            result = Some(match (self, result) {
+                (_, None) => value,
+                (Aggregation::Min, Some(current)) => current.min(value),
+                (Aggregation::Max, Some(current)) => current.max(value),
+                (Aggregation::Mean, Some(current)) => current + value
+            });
+        }
+
+        match self {
+            Aggregation::Mean => result.map(|sum| sum / count as f32),
+            _ => result
+        }
+    }
+}
+
+/// Values recorded at one point in time; `None` where the device didn't report.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct EnvironmentSample {
+    pub aqi: Option<f32>,
+    pub temperature: Option<f32>,
+    pub humidity: Option<f32>,
+    pub pm25: Option<f32>,
+    pub pm10: Option<f32>,
+    pub voc: Option<f32>,
+    pub no2: Option<f32>,
+    pub usage: Option<f32>
+}
+
+impl EnvironmentSample {
+    pub fn is_empty(&self) -> bool {
+        *self == EnvironmentSample::default()
+    }
+
+    /// Combines the samples field by field, skipping missing values.
+    pub fn aggregate(samples: &[EnvironmentSample], aggregation: Aggregation) -> Self {
+        let field = |value: fn(&EnvironmentSample) -> Option<f32>| {
+            aggregation.apply(samples.iter().filter_map(value))
+        };
+
+        EnvironmentSample {
+            aqi: field(|sample| sample.aqi),
+            temperature: field(|sample| sample.temperature),
+            humidity: field(|sample| sample.humidity),
+            pm25: field(|sample| sample.pm25),
+            pm10: field(|sample| sample.pm10),
+            voc: field(|sample| sample.voc),
+            no2: field(|sample| sample.no2),
+            usage: field(|sample| sample.usage)
+        }
+    }
+}
+
+/// Timestamped samples in ascending order.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct EnvironmentTimeSeries {
+    pub points: Vec<(DateTime<Utc>, EnvironmentSample)>
+}
+
+impl EnvironmentTimeSeries {
+    pub fn iter(&self) -> std::slice::Iter<'_, (DateTime<Utc>, EnvironmentSample)> {
+        self.points.iter()
+    }
+
+    /// Points with `from <= time < to`.
+    pub fn window(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> impl Iterator<Item = &(DateTime<Utc>, EnvironmentSample)> {
+        self.points.iter().filter(move |(time, _)| *time >= from && *time < to)
+    }
+
+    pub fn aggregate(&self, from: DateTime<Utc>, to: DateTime<Utc>, aggregation: Aggregation) -> EnvironmentSample {
+        let samples = self.window(from, to)
+            .map(|(_, sample)| *sample)
+            .collect::<Vec<_>>();
+
+        EnvironmentSample::aggregate(&samples, aggregation)
+    }
+
+    /// Groups points into buckets of `interval` aligned to the Unix epoch.
+    /// Buckets without any point are left out, so gaps stay visible.
+    pub fn resample(&self, interval: Duration, aggregation: Aggregation) -> Self {
+        let seconds = interval.num_seconds().max(1);
+        let mut points: Vec<(DateTime<Utc>, EnvironmentSample)> = vec![];
+        let mut bucket: Vec<EnvironmentSample> = vec![];
+        let mut bucket_start: Option<i64> = None;
+
+        for (time, sample) in self.points.iter() {
+            let start = time.timestamp().div_euclid(seconds) * seconds;
+
+            if let Some(current) = bucket_start.filter(|current| *current != start) {
+                points.push((timestamp(current), EnvironmentSample::aggregate(&bucket, aggregation)));
+                bucket.clear();
+            }
+
+            bucket_start = Some(start);
+            bucket.push(*sample);
+        }
+
+        if let Some(current) = bucket_start {
+            points.push((timestamp(current), EnvironmentSample::aggregate(&bucket, aggregation)));
+        }
+
+        EnvironmentTimeSeries { points }
+    }
+
+    /// Ranges of consecutive points that have no value at all, as `(first, last)`.
+    pub fn gaps(&self) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+        let mut gaps = vec![];
+        let mut current: Option<(DateTime<Utc>, DateTime<Utc>)> = None;
+
+        for (time, sample) in self.points.iter() {
+            if sample.is_empty() {
+                current = Some(match current {
+                    Some((first, _)) => (first, *time),
+                    None => (*time, *time)
+                });
+            } else if let Some(gap) = current.take() {
+                gaps.push(gap);
+            }
+        }
+
+        gaps.extend(current);
+        gaps
+    }
+}
+
+/// Environment history in the same shape whichever cloud endpoint it was read from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnvironmentHistory {
+    pub resolution: HistoryResolution,
+    pub periods: Vec<EnvironmentHistoryPeriod>
+}
+
+/// Evenly spaced samples starting at `date`; temperature is in Celsius.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct EnvironmentHistoryPeriod {
+    pub date: String,
+    pub aqi: Vec<Option<f32>>,
+    pub temperature: Vec<Option<f32>>,
+    pub humidity: Vec<Option<f32>>,
+    pub pm25: Vec<Option<f32>>,
+    pub pm10: Vec<Option<f32>>,
+    pub voc: Vec<Option<f32>>,
+    pub no2: Vec<Option<f32>>,
+    pub usage: Vec<Option<f32>>
+}
+
+impl EnvironmentHistory {
+    /// Spreads the samples of every period evenly over its length, e.g. 24 samples
+    /// of a daily period become hourly points. Periods with an unreadable date are skipped.
+    pub fn to_time_series(&self) -> EnvironmentTimeSeries {
+        let mut points = self.periods.iter()
+            .filter_map(|period| period.start().map(|start| (start, period)))
+            .flat_map(|(start, period)| {
+                let count = period.len();
+                let step = self.resolution.period_length() / count.max(1) as i32;
+
+                (0..count).map(move |index| (start + step * index as i32, period.sample(index)))
+            })
+            .collect::<Vec<_>>();
+
+        points.sort_by_key(|(time, _)| *time);
+        EnvironmentTimeSeries { points }
+    }
+
+    pub fn from_history(resolution: HistoryResolution, history: &EnvironmentDataHistory) -> Self {
+        let periods = history.data.iter()
+            .map(|period| EnvironmentHistoryPeriod {
+                date: period.date.clone(),
+                aqi: period.aqi.clone(),
+                temperature: period.temperature.clone(),
+                humidity: period.humidity.clone(),
+                pm25: period.pm25.clone(),
+                pm10: period.pm10.clone(),
+                voc: period.voc.clone(),
+                no2: period.no2.clone(),
+                usage: period.usage.clone()
+            })
+            .collect();
+
+        EnvironmentHistory { resolution, periods }
+    }
+
+    pub fn from_legacy_daily(daily: &[EnvironmentDataDaily]) -> Self {
+        let periods = daily.iter()
+            .map(|day| EnvironmentHistoryPeriod {
+                date: day.date.clone(),
+                aqi: day.aqi.clone(),
+                temperature: to_f32_series(&day.temperature),
+                humidity: to_f32_series(&day.humidity),
+                usage: to_f32_series(&day.usage),
+                ..Default::default()
+            })
+            .collect();
+
+        EnvironmentHistory { resolution: HistoryResolution::Daily, periods }
+    }
+
+    pub fn from_legacy_weekly(weekly: &[EnvironmentDataWeekly]) -> Self {
+        let periods = weekly.iter()
+            .map(|week| EnvironmentHistoryPeriod {
+                date: week.date.clone(),
+                aqi: week.aqi.clone(),
+                temperature: to_f32_series(&week.temperature),
+                humidity: to_f32_series(&week.humidity),
+                usage: to_f32_series(&week.usage),
+                ..Default::default()
+            })
+            .collect();
+
+        EnvironmentHistory { resolution: HistoryResolution::Weekly, periods }
+    }
+}
+
+impl EnvironmentHistoryPeriod {
+    /// Start of the period; the cloud sends either a plain date or a UTC date time.
+    pub fn start(&self) -> Option<DateTime<Utc>> {
+        if let Ok(date) = NaiveDate::parse_from_str(&self.date, "%Y-%m-%d") {
+            return date.and_hms_opt(0, 0, 0).map(|date_time| Utc.from_utc_datetime(&date_time));
+        }
+
+        DateTime::parse_from_rfc3339(&self.date)
+            .map(|date_time| date_time.with_timezone(&Utc))
+            .or_else(|_| NaiveDateTime::parse_from_str(&self.date, "%Y-%m-%dT%H:%M:%S")
+                .map(|date_time| Utc.from_utc_datetime(&date_time)))
+            .ok()
+    }
+
+    /// Number of samples, taken from the longest series.
+    pub fn len(&self) -> usize {
+        [&self.aqi, &self.temperature, &self.humidity, &self.pm25, &self.pm10, &self.voc, &self.no2, &self.usage]
+            .iter()
+            .map(|series| series.len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn sample(&self, index: usize) -> EnvironmentSample {
+        let value = |series: &Vec<Option<f32>>| series.get(index).copied().flatten();
+
+        EnvironmentSample {
+            aqi: value(&self.aqi),
+            temperature: value(&self.temperature),
+            humidity: value(&self.humidity),
+            pm25: value(&self.pm25),
+            pm10: value(&self.pm10),
+            voc: value(&self.voc),
+            no2: value(&self.no2),
+            usage: value(&self.usage)
+        }
+    }
+}
+
+fn timestamp(seconds: i64) -> DateTime<Utc> {
+    Utc.timestamp_opt(seconds, 0).unwrap()
+}
+
+fn to_f32_series(values: &[Option<i32>]) -> Vec<Option<f32>> {
+    values.iter()
+        .map(|value| value.map(|value| value as f32))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_converts_both_endpoints_to_the_same_history() {
+        let legacy: Vec<EnvironmentDataDaily> = serde_json::from_str(r#"
+            [{
+                "Aqi": [1.0, null],
+                "AverageAqi": 1.0,
+                "AverageHumidity": 45,
+                "Date": "2020-05-19",
+                "Humidity": [45, null],
+                "MaxTemperature": 23,
+                "MinTemperature": 23,
+                "Temperature": [23, null],
+                "TotalUsage": 60,
+                "Usage": [60, null]
+            }]"#).unwrap();
+        let history: EnvironmentDataHistory = serde_json::from_str(r#"
+            {
+                "data": [{
+                    "date": "2020-05-19",
+                    "aqi": [1.0, null],
+                    "temperature": [23.0, null],
+                    "humidity": [45.0, null],
+                    "usage": [60.0, null]
+                }]
+            }"#).unwrap();
+
+        let from_legacy = EnvironmentHistory::from_legacy_daily(&legacy);
+        let from_history = EnvironmentHistory::from_history(HistoryResolution::Daily, &history);
+
+        assert_eq!(from_legacy, from_history);
+        assert_eq!(from_history.periods[0].temperature, vec![Some(23.0), None]);
+        assert!(from_history.periods[0].pm25.is_empty());
+    }
+
+    fn day(date: &str, temperature: Vec<Option<f32>>) -> EnvironmentHistoryPeriod {
+        EnvironmentHistoryPeriod {
+            date: String::from(date),
+            temperature,
+            ..Default::default()
+        }
+    }
+
+    fn time(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn it_spreads_samples_over_the_period() {
+        let history = EnvironmentHistory {
+            resolution: HistoryResolution::Daily,
+            periods: vec![day("2020-05-20", vec![Some(21.0); 24]), day("2020-05-19", vec![Some(20.0); 24])]
+        };
+
+        let actual = history.to_time_series();
+
+        assert_eq!(actual.points.len(), 48);
+        assert_eq!(actual.points[0].0, time("2020-05-19T00:00:00Z"));
+        assert_eq!(actual.points[1].0, time("2020-05-19T01:00:00Z"));
+        assert_eq!(actual.points[24], (time("2020-05-20T00:00:00Z"), EnvironmentSample { temperature: Some(21.0), ..Default::default() }));
+    }
+
+    #[test]
+    fn it_resamples_and_reports_gaps() {
+        let history = EnvironmentHistory {
+            resolution: HistoryResolution::Daily,
+            periods: vec![day("2020-05-19", vec![Some(18.0), Some(20.0), None, None, Some(22.0), Some(26.0)])]
+        };
+        let series = history.to_time_series();
+
+        let actual = series.resample(Duration::hours(8), Aggregation::Mean);
+
+        let temperatures = actual.iter().map(|(_, sample)| sample.temperature).collect::<Vec<_>>();
+        assert_eq!(temperatures, vec![Some(19.0), None, Some(24.0)]);
+        assert_eq!(series.gaps(), vec![(time("2020-05-19T08:00:00Z"), time("2020-05-19T12:00:00Z"))]);
+    }
+
+    #[test]
+    fn it_aggregates_over_a_window() {
+        let history = EnvironmentHistory {
+            resolution: HistoryResolution::Weekly,
+            periods: vec![day("2020-05-18", vec![Some(18.0), None, Some(20.0), Some(25.0), Some(30.0), Some(19.0), Some(17.0)])]
+        };
+        let series = history.to_time_series();
+        let (from, to) = (time("2020-05-18T00:00:00Z"), time("2020-05-21T00:00:00Z"));
+
+        assert_eq!(series.aggregate(from, to, Aggregation::Min).temperature, Some(18.0));
+        assert_eq!(series.aggregate(from, to, Aggregation::Max).temperature, Some(20.0));
+        assert_eq!(series.aggregate(from, to, Aggregation::Mean).temperature, Some(19.0));
+        assert_eq!(series.aggregate(from, to, Aggregation::Mean).humidity, None);
+    }
+}
@@ -2,6 +2,16 @@ pub mod api;
 pub mod model;
 pub mod mqtt;
 pub mod api_error;
+pub mod device;
+pub mod command;
+pub mod control;
+pub mod store;
+pub mod discovery;
+pub mod history;
+pub mod product;
+pub mod state;
+pub mod thermostat;
+pub mod units;
+pub mod aqi;
+pub mod comfort;
 mod util;
-mod unit_conversion;
-mod control;
@@ -1,5 +1,10 @@
 use serde::{Deserialize, Serialize};
 
+use super::api::DysonClient;
+use super::api_error::*;
+use super::product::ProductType;
+use super::util::*;
+
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct UserCredentials {
     pub email: String,
@@ -15,6 +20,46 @@ pub struct AccountCredentials {
     pub password: String,
 }
 
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct TokenCredentials {
+    pub account: String,
+    pub token: String,
+    #[serde(rename = "tokenType")]
+    pub token_type: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub enum Authentication {
+    Account(AccountCredentials),
+    Token(TokenCredentials),
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct DysonSession {
+    pub authentication: Authentication,
+    pub country_code: String,
+    /// Cloud endpoint the credentials belong to; sessions saved without it resume against the global API.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_url: Option<String>,
+    pub device_manifest: Vec<DeviceManifest>,
+    /// When the stored credentials were issued; resuming and saving again keeps it.
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct UserStatus {
+    #[serde(rename = "accountStatus")]
+    pub account_status: String,
+    #[serde(rename = "authenticationMethod")]
+    pub authentication_method: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct OtpChallenge {
+    #[serde(rename = "challengeId")]
+    pub challenge_id: String,
+}
+
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct DeviceManifest {
     #[serde(rename = "Serial")]
@@ -32,7 +77,53 @@ pub struct DeviceManifest {
     #[serde(rename = "ProductType")]
     pub product_type: String,
     #[serde(rename = "ConnectionType")]
-    pub connection_type: String
+    pub connection_type: String,
+    /// Sent by newer manifests, with broker credentials encrypted like `local_credentials`.
+    #[serde(rename = "connectedConfiguration", default, skip_serializing_if = "Option::is_none")]
+    pub connected_configuration: Option<ConnectedConfiguration>
+}
+
+impl DeviceManifest {
+    /// `None` for product codes this crate doesn't know yet.
+    pub fn product(&self) -> Option<ProductType> {
+        ProductType::from_code(&self.product_type)
+    }
+
+    /// Credentials for the device's local MQTT broker.
+    pub fn local_mqtt_credentials(&self) -> Result<LocalMqttCredentials, DecryptCredentialsError> {
+        Ok(self.decrypt_local_credentials()?.mqtt_credentials())
+    }
+
+    /// Prefers the broker credentials of `connected_configuration` over `local_credentials`.
+    pub fn decrypt_local_credentials(&self) -> Result<DecryptedLocalCredentials, DecryptCredentialsError> {
+        let local_credentials = self.connected_configuration.as_ref()
+            .and_then(|connected_configuration| connected_configuration.mqtt.as_ref())
+            .map_or(&self.local_credentials, |mqtt| &mqtt.local_broker_credentials);
+
+        DysonClient::decrypt_local_credentials(local_credentials)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Default)]
+pub struct ConnectedConfiguration {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mqtt: Option<MqttConfiguration>
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct MqttConfiguration {
+    #[serde(rename = "localBrokerCredentials")]
+    pub local_broker_credentials: String,
+    #[serde(rename = "mqttRootTopicLevel", default, skip_serializing_if = "Option::is_none")]
+    pub root_topic_level: Option<String>
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct LocalMqttCredentials {
+    #[serde(rename = "Username")]
+    pub username: String,
+    #[serde(rename = "Password")]
+    pub password: String
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Default)]
@@ -42,6 +133,16 @@ pub struct DecryptedLocalCredentials {
     pub access_point_password_hash: String
 }
 
+impl DecryptedLocalCredentials {
+    /// The broker expects the serial as user name and the base64 SHA-512 of `apPasswordHash` as password.
+    pub fn mqtt_credentials(&self) -> LocalMqttCredentials {
+        LocalMqttCredentials {
+            username: self.serial.clone(),
+            password: sha512_base64(&self.access_point_password_hash)
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct EnvironmentData {
     #[serde(rename = "AqiDescription")]
@@ -109,69 +210,100 @@ pub struct EnvironmentDataHelp {
     #[serde(rename = "PollenGuidelineUrl")]
     pollen_guideline_url: String,
     #[serde(rename = "Ranges")]
-    ranges: Vec<EnvironmentRangeHelp>,
+    pub ranges: Vec<EnvironmentRangeHelp>,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct EnvironmentRangeHelp {
     #[serde(rename = "Range")]
-    range: String,
+    pub range: String,
     #[serde(rename = "Name")]
-    name: String,
+    pub name: String,
     #[serde(rename = "Description")]
-    description: String,
+    pub description: String,
     #[serde(rename = "ColorIndex")]
-    color_index: String,
+    pub color_index: String,
     #[serde(rename = "ColorValue")]
-    color_value: Option<String>
+    pub color_value: Option<String>
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct EnvironmentDataDaily {
     #[serde(rename = "Aqi")]
-    aqi: Vec<Option<f32>>,
+    pub aqi: Vec<Option<f32>>,
     #[serde(rename = "AverageAqi")]
-    average_aqi: Option<f32>,
+    pub average_aqi: Option<f32>,
     #[serde(rename = "AverageHumidity")]
-    average_humidity: Option<i32>,
+    pub average_humidity: Option<i32>,
     #[serde(rename = "Date")]
-    date: String,
+    pub date: String,
     #[serde(rename = "Humidity")]
-    humidity: Vec<Option<i32>>,
+    pub humidity: Vec<Option<i32>>,
     #[serde(rename = "MaxTemperature")]
-    max_temperature: Option<i32>,
+    pub max_temperature: Option<i32>,
     #[serde(rename = "MinTemperature")]
-    min_temperature: Option<i32>,
+    pub min_temperature: Option<i32>,
     #[serde(rename = "Temperature")]
-    temperature: Vec<Option<i32>>,
+    pub temperature: Vec<Option<i32>>,
     #[serde(rename = "TotalUsage")]
-    total_usage: Option<i32>,
+    pub total_usage: Option<i32>,
     #[serde(rename = "Usage")]
-    usage: Vec<Option<i32>>
+    pub usage: Vec<Option<i32>>
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct EnvironmentDataWeekly {
     #[serde(rename = "Aqi")]
-    aqi: Vec<Option<f32>>,
+    pub aqi: Vec<Option<f32>>,
     #[serde(rename = "AverageAqi")]
-    average_aqi: Option<f32>,
+    pub average_aqi: Option<f32>,
     #[serde(rename = "Date")]
-    date: String,
+    pub date: String,
     #[serde(rename = "Humidity")]
-    humidity: Vec<Option<i32>>,
+    pub humidity: Vec<Option<i32>>,
     #[serde(rename = "MaxHumidity")]
-    max_humidity: Option<i32>,
+    pub max_humidity: Option<i32>,
     #[serde(rename = "MaxTemperature")]
-    max_temperature: Option<i32>,
+    pub max_temperature: Option<i32>,
     #[serde(rename = "MinHumidity")]
-    min_humidity: Option<i32>,
+    pub min_humidity: Option<i32>,
     #[serde(rename = "MinTemperature")]
-    min_temperature: Option<i32>,
+    pub min_temperature: Option<i32>,
     #[serde(rename = "Temperature")]
-    temperature: Vec<Option<i32>>,
+    pub temperature: Vec<Option<i32>>,
     #[serde(rename = "TotalUsage")]
-    total_usage: Option<i32>,
+    pub total_usage: Option<i32>,
     #[serde(rename = "Usage")]
-    usage: Vec<Option<i32>>
+    pub usage: Vec<Option<i32>>
+}
+
+/// Response of the `environmentdata/daily` and `environmentdata/weekly` endpoints.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct EnvironmentDataHistory {
+    #[serde(rename = "data", default)]
+    pub data: Vec<EnvironmentDataHistoryPeriod>
+}
+
+/// One day (daily endpoint) or week (weekly endpoint) of evenly spaced samples.
+/// Series the device can't measure are missing from the payload and left empty.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct EnvironmentDataHistoryPeriod {
+    #[serde(rename = "date")]
+    pub date: String,
+    #[serde(rename = "aqi", default)]
+    pub aqi: Vec<Option<f32>>,
+    #[serde(rename = "temperature", default)]
+    pub temperature: Vec<Option<f32>>,
+    #[serde(rename = "humidity", default)]
+    pub humidity: Vec<Option<f32>>,
+    #[serde(rename = "pm25", default)]
+    pub pm25: Vec<Option<f32>>,
+    #[serde(rename = "pm10", default)]
+    pub pm10: Vec<Option<f32>>,
+    #[serde(rename = "voc", default)]
+    pub voc: Vec<Option<f32>>,
+    #[serde(rename = "no2", default)]
+    pub no2: Vec<Option<f32>>,
+    #[serde(rename = "usage", default)]
+    pub usage: Vec<Option<f32>>
 }
@@ -1,5 +1,13 @@
+use std::time::Duration;
+
 use serde::{Deserialize, Serialize, Deserializer};
+use super::comfort::*;
 use super::control::*;
+use super::units::*;
+use super::util::*;
+
+pub const REQUEST_CURRENT_STATE: &str = "REQUEST-CURRENT-STATE";
+pub const REQUEST_ENVIRONMENT_CURRENT_SENSOR_DATA: &str = "REQUEST-PRODUCT-ENVIRONMENT-CURRENT-SENSOR-DATA";
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct RequestPayload {
@@ -8,6 +16,15 @@ pub struct RequestPayload {
     pub time: String
 }
 
+impl RequestPayload {
+    pub fn new(message: &str) -> Self {
+        RequestPayload {
+            message: String::from(message),
+            time: current_timestamp()
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct ConnectionStatus {
     #[serde(rename = "msg")]
@@ -40,8 +57,8 @@ pub struct ConnectionStatus {
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct EnvironmentalCurrentSensorRaw {
-    time: String,
-    data: EnvironmentalCurrentSensorDataRaw
+    pub time: String,
+    pub data: EnvironmentalCurrentSensorDataRaw
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
@@ -55,29 +72,131 @@ pub struct EnvironmentalCurrentSensorDataRaw {
 
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct EnvironmentCurrentSensorData {
-    pub humidity_percentage: f32,
+    /// `None` while the sensor warms up (`INIT`) or is off.
+    pub humidity_percentage: Option<f32>,
     pub dust: f32,
-    pub sleep_timer: f32,
-    pub temperature_kelvin: f32,
+    pub sleep_timer: Option<Duration>,
+    /// `None` while the sensor warms up (`INIT`) or is off.
+    pub temperature: Option<Temperature>,
     pub volatile_organic_compounds_ppm: f32,
 }
 
 impl EnvironmentCurrentSensorData {
     pub fn from_raw(raw: &EnvironmentalCurrentSensorDataRaw) -> Self {
-        let temperature_kelvin = raw.tact.parse::<f32>().unwrap_or(0.0) / 10.0;
-        let humidity_percentage = raw.hact.parse::<f32>().unwrap_or(0.0);
+        let temperature = parse_deci_kelvin(&raw.tact).map(Temperature::from_kelvin);
+        let humidity_percentage = parse_sensor(Some(&raw.hact), 1.0);
         let dust = raw.pact.parse::<f32>().unwrap_or(0.0);
         let volatile_organic_compounds_ppm = raw.vact.parse::<f32>().unwrap_or(0.0);
-        let sleep_timer = raw.sltm.parse::<f32>().unwrap_or(0.0);
+        let sleep_timer = parse_sleep_timer(&raw.sltm);
 
         Self {
             humidity_percentage,
             dust,
             sleep_timer,
-            temperature_kelvin,
+            temperature,
             volatile_organic_compounds_ppm
         }
     }
+
+    pub fn dew_point_kelvin(&self) -> Option<f32> {
+        let (temperature_kelvin, humidity_percentage) = self.climate()?;
+        dew_point_kelvin(temperature_kelvin, humidity_percentage)
+    }
+
+    pub fn absolute_humidity(&self) -> Option<f32> {
+        self.climate().map(|(temperature_kelvin, humidity_percentage)| absolute_humidity(temperature_kelvin, humidity_percentage))
+    }
+
+    pub fn heat_index_kelvin(&self) -> Option<f32> {
+        self.climate().map(|(temperature_kelvin, humidity_percentage)| heat_index_kelvin(temperature_kelvin, humidity_percentage))
+    }
+
+    pub fn humidex(&self) -> Option<f32> {
+        let (temperature_kelvin, humidity_percentage) = self.climate()?;
+        humidex(temperature_kelvin, humidity_percentage)
+    }
+
+    pub fn comfort(&self) -> Option<Comfort> {
+        self.humidity_percentage.map(Comfort::from_humidity)
+    }
+
+    fn climate(&self) -> Option<(f32, f32)> {
+        Some((self.temperature?.kelvin(), self.humidity_percentage?))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PurifierEnvironmentalSensorRaw {
+    pub time: String,
+    pub data: PurifierEnvironmentalSensorDataRaw
+}
+
+/// Sensor fields of the purifier generation; each model only sends the sensors it has.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Default)]
+#[serde(default)]
+pub struct PurifierEnvironmentalSensorDataRaw {
+    pub tact: Option<String>,
+    pub hact: Option<String>,
+    pub pm25: Option<String>,
+    pub pm10: Option<String>,
+    pub p25r: Option<String>,
+    pub p10r: Option<String>,
+    pub va10: Option<String>,
+    pub noxl: Option<String>,
+    pub hcho: Option<String>,
+    pub hchr: Option<String>,
+    pub co2r: Option<String>,
+    pub sltm: Option<String>,
+}
+
+/// Converted sensor data; `None` where the sensor is missing, warming up (`INIT`) or off.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct PurifierEnvironmentCurrentSensorData {
+    pub temperature: Option<Temperature>,
+    pub humidity_percentage: Option<f32>,
+    pub pm25: Option<f32>,
+    pub pm10: Option<f32>,
+    pub pm25_raw: Option<f32>,
+    pub pm10_raw: Option<f32>,
+    pub volatile_organic_compounds_index: Option<f32>,
+    pub nitrogen_dioxide_index: Option<f32>,
+    pub formaldehyde_mg_per_m3: Option<f32>,
+    pub formaldehyde_raw_mg_per_m3: Option<f32>,
+    pub carbon_dioxide_ppm: Option<f32>,
+    pub sleep_timer: Option<Duration>,
+}
+
+impl PurifierEnvironmentCurrentSensorData {
+    pub fn from_raw(raw: &PurifierEnvironmentalSensorDataRaw) -> Self {
+        Self {
+            temperature: raw.tact.as_deref().and_then(parse_deci_kelvin).map(Temperature::from_kelvin),
+            humidity_percentage: parse_sensor(raw.hact.as_deref(), 1.0),
+            pm25: parse_sensor(raw.pm25.as_deref(), 1.0),
+            pm10: parse_sensor(raw.pm10.as_deref(), 1.0),
+            pm25_raw: parse_sensor(raw.p25r.as_deref(), 1.0),
+            pm10_raw: parse_sensor(raw.p10r.as_deref(), 1.0),
+            volatile_organic_compounds_index: parse_sensor(raw.va10.as_deref(), 10.0),
+            nitrogen_dioxide_index: parse_sensor(raw.noxl.as_deref(), 10.0),
+            formaldehyde_mg_per_m3: parse_sensor(raw.hcho.as_deref(), 1000.0),
+            formaldehyde_raw_mg_per_m3: parse_sensor(raw.hchr.as_deref(), 1000.0),
+            carbon_dioxide_ppm: parse_sensor(raw.co2r.as_deref(), 1.0),
+            sleep_timer: raw.sltm.as_deref().and_then(parse_sleep_timer),
+        }
+    }
+}
+
+/// Remaining sleep timer from `sltm` minutes; `None` when it reads `OFF`.
+pub(crate) fn parse_sleep_timer(value: &str) -> Option<Duration> {
+    value.parse::<u64>().ok()
+        .filter(|minutes| *minutes > 0)
+        .map(|minutes| Duration::from_secs(minutes * 60))
+}
+
+/// Divides numeric readings by `scale`; sentinels such as `INIT`, `OFF` or `NONE` give `None`.
+pub(crate) fn parse_sensor(value: Option<&str>, scale: f32) -> Option<f32> {
+    value
+        .and_then(|value| value.parse::<f32>().ok())
+        .map(|value| value / scale)
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -138,6 +257,569 @@ pub struct SchedulerRaw {
     pub tzid: String,
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateChangeRaw {
+    pub time: String,
+    #[serde(rename = "mode-reason")]
+    pub mode_reason: String,
+    #[serde(rename = "state-reason")]
+    pub state_reason: String,
+    #[serde(rename = "product-state")]
+    pub product_state: ProductStateChange,
+    #[serde(default)]
+    pub scheduler: Option<SchedulerRaw>,
+}
+
+/// CURRENT-STATE of the purifier generation (TP04, DP04, HP04 and newer).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PurifierCurrentStateRaw {
+    pub time: String,
+    #[serde(rename = "mode-reason")]
+    pub mode_reason: String,
+    #[serde(rename = "state-reason")]
+    pub state_reason: String,
+    #[serde(deserialize_with = "from_string")]
+    pub rssi: i32,
+    #[serde(rename = "product-state")]
+    pub product_state: PurifierProductState,
+    #[serde(default)]
+    pub scheduler: Option<SchedulerRaw>,
+}
+
+/// Values the device reports as `OFF`, `INV` or `INIT` are `None`, as are fields the model lacks.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PurifierProductState {
+    #[serde(rename = "fpwr")]
+    pub fan_power: FanPower,
+    #[serde(rename = "fnst")]
+    pub fan_state: FanState,
+    #[serde(rename = "fnsp")]
+    pub fan_speed: FanSpeed,
+    #[serde(rename = "auto")]
+    pub auto_mode: AutoMode,
+    #[serde(rename = "oscs")]
+    pub oscillation_state: OscillationState,
+    #[serde(rename = "oson")]
+    pub oscillation_status: OscillationStatus,
+    #[serde(rename = "osal", default, deserialize_with = "from_optional_string")]
+    pub oscillation_angle_low: Option<u16>,
+    #[serde(rename = "osau", default, deserialize_with = "from_optional_string")]
+    pub oscillation_angle_high: Option<u16>,
+    #[serde(rename = "ancp", default)]
+    pub oscillation_preset: Option<String>,
+    #[serde(rename = "nmod")]
+    pub night_mode: NightMode,
+    #[serde(rename = "rhtm")]
+    pub air_quality_monitoring_status: AirQualityMonitoringStatus,
+    #[serde(rename = "fdir", default)]
+    pub front_airflow: Option<FrontAirflow>,
+    #[serde(rename = "bril", default, deserialize_with = "from_optional_string")]
+    pub brightness: Option<u16>,
+    #[serde(rename = "cflr", default, deserialize_with = "from_optional_string")]
+    pub carbon_filter_life: Option<i32>,
+    #[serde(rename = "hflr", default, deserialize_with = "from_optional_string")]
+    pub hepa_filter_life: Option<i32>,
+    #[serde(rename = "sltm", default, deserialize_with = "from_optional_string")]
+    pub sleep_timer: Option<u32>,
+    #[serde(rename = "ercd")]
+    pub ercd: String,
+    #[serde(rename = "wacd")]
+    pub wacd: String,
+    #[serde(rename = "hmod", default)]
+    pub heat_mode: Option<HeatMode>,
+    #[serde(rename = "hmax", default, deserialize_with = "from_optional_raw_string_to_kelvin")]
+    pub heat_target_kelvin: Option<f32>,
+    #[serde(rename = "hsta", default)]
+    pub heat_state: Option<HeatState>,
+    #[serde(rename = "tilt", default)]
+    pub tilt_state: Option<TiltState>,
+    #[serde(rename = "hume", default)]
+    pub humidify_mode: Option<HumidifyMode>,
+    #[serde(rename = "haut", default)]
+    pub auto_humidity: Option<AutoHumidity>,
+    #[serde(rename = "humt", default, deserialize_with = "from_optional_string")]
+    pub humidity_target: Option<u8>,
+    #[serde(rename = "rect", default, deserialize_with = "from_optional_string")]
+    pub auto_humidity_target: Option<u8>,
+    #[serde(rename = "wath", default)]
+    pub water_hardness: Option<WaterHardness>,
+    #[serde(rename = "clcr", default)]
+    pub clean_cycle: Option<CleanCycle>,
+    #[serde(rename = "cltr", default, deserialize_with = "from_optional_string")]
+    pub time_until_clean_hours: Option<u32>,
+    #[serde(rename = "cdrr", default, deserialize_with = "from_optional_string")]
+    pub clean_time_remaining_minutes: Option<u32>,
+}
+
+impl PurifierProductState {
+    pub const FIELDS: [ProductStateField; 30] = [
+        ProductStateField::FanPower,
+        ProductStateField::FanState,
+        ProductStateField::FanSpeed,
+        ProductStateField::AutoMode,
+        ProductStateField::OscillationState,
+        ProductStateField::OscillationStatus,
+        ProductStateField::OscillationAngleLow,
+        ProductStateField::OscillationAngleHigh,
+        ProductStateField::OscillationPreset,
+        ProductStateField::NightMode,
+        ProductStateField::AirQualityMonitoringStatus,
+        ProductStateField::FrontAirflow,
+        ProductStateField::Brightness,
+        ProductStateField::CarbonFilterLife,
+        ProductStateField::HepaFilterLife,
+        ProductStateField::SleepTimer,
+        ProductStateField::ErrorCode,
+        ProductStateField::WarningCode,
+        ProductStateField::HeatMode,
+        ProductStateField::HeatTarget,
+        ProductStateField::HeatState,
+        ProductStateField::TiltState,
+        ProductStateField::HumidifyMode,
+        ProductStateField::AutoHumidity,
+        ProductStateField::HumidityTarget,
+        ProductStateField::AutoHumidityTarget,
+        ProductStateField::WaterHardness,
+        ProductStateField::CleanCycle,
+        ProductStateField::TimeUntilClean,
+        ProductStateField::CleanTimeRemaining,
+    ];
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PurifierStateChangeRaw {
+    pub time: String,
+    #[serde(rename = "mode-reason")]
+    pub mode_reason: String,
+    #[serde(rename = "state-reason")]
+    pub state_reason: String,
+    #[serde(rename = "product-state")]
+    pub product_state: PurifierProductStateChange,
+    #[serde(default)]
+    pub scheduler: Option<SchedulerRaw>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ProductStateField {
+    FanMode,
+    FanState,
+    FanSpeed,
+    QualityTarget,
+    OscillationStatus,
+    AirQualityMonitoringStatus,
+    FilterLife,
+    ErrorCode,
+    NightMode,
+    WarningCode,
+    HeatMode,
+    HeatTarget,
+    HeatState,
+    FanFocusMode,
+    TiltState,
+    FanPower,
+    AutoMode,
+    OscillationState,
+    OscillationAngleLow,
+    OscillationAngleHigh,
+    OscillationPreset,
+    FrontAirflow,
+    Brightness,
+    CarbonFilterLife,
+    HepaFilterLife,
+    SleepTimer,
+    HumidifyMode,
+    AutoHumidity,
+    HumidityTarget,
+    AutoHumidityTarget,
+    WaterHardness,
+    CleanCycle,
+    TimeUntilClean,
+    CleanTimeRemaining,
+}
+
+impl ProductStateField {
+    pub const ALL: [ProductStateField; 34] = [
+        ProductStateField::FanMode,
+        ProductStateField::FanState,
+        ProductStateField::FanSpeed,
+        ProductStateField::QualityTarget,
+        ProductStateField::OscillationStatus,
+        ProductStateField::AirQualityMonitoringStatus,
+        ProductStateField::FilterLife,
+        ProductStateField::ErrorCode,
+        ProductStateField::NightMode,
+        ProductStateField::WarningCode,
+        ProductStateField::HeatMode,
+        ProductStateField::HeatTarget,
+        ProductStateField::HeatState,
+        ProductStateField::FanFocusMode,
+        ProductStateField::TiltState,
+        ProductStateField::FanPower,
+        ProductStateField::AutoMode,
+        ProductStateField::OscillationState,
+        ProductStateField::OscillationAngleLow,
+        ProductStateField::OscillationAngleHigh,
+        ProductStateField::OscillationPreset,
+        ProductStateField::FrontAirflow,
+        ProductStateField::Brightness,
+        ProductStateField::CarbonFilterLife,
+        ProductStateField::HepaFilterLife,
+        ProductStateField::SleepTimer,
+        ProductStateField::HumidifyMode,
+        ProductStateField::AutoHumidity,
+        ProductStateField::HumidityTarget,
+        ProductStateField::AutoHumidityTarget,
+        ProductStateField::WaterHardness,
+        ProductStateField::CleanCycle,
+        ProductStateField::TimeUntilClean,
+        ProductStateField::CleanTimeRemaining,
+    ];
+}
+
+impl ProductState {
+    /// Fields reported by the Link generation.
+    pub const FIELDS: [ProductStateField; 15] = [
+        ProductStateField::FanMode,
+        ProductStateField::FanState,
+        ProductStateField::FanSpeed,
+        ProductStateField::QualityTarget,
+        ProductStateField::OscillationStatus,
+        ProductStateField::AirQualityMonitoringStatus,
+        ProductStateField::FilterLife,
+        ProductStateField::ErrorCode,
+        ProductStateField::NightMode,
+        ProductStateField::WarningCode,
+        ProductStateField::HeatMode,
+        ProductStateField::HeatTarget,
+        ProductStateField::HeatState,
+        ProductStateField::FanFocusMode,
+        ProductStateField::TiltState,
+    ];
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValueChange<T> {
+    pub old: T,
+    pub new: T,
+}
+
+impl<T: PartialEq> ValueChange<T> {
+    pub fn is_changed(&self) -> bool {
+        self.old != self.new
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for ValueChange<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let (old, new) = <(T, T)>::deserialize(deserializer)?;
+        Ok(ValueChange { old, new })
+    }
+}
+
+impl<T: Serialize> Serialize for ValueChange<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: serde::Serializer
+    {
+        (&self.old, &self.new).serialize(serializer)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProductStateChange {
+    #[serde(rename = "fmod")]
+    pub fan_mode: Option<ValueChange<FanMode>>,
+    #[serde(rename = "fnst")]
+    pub fan_state: Option<ValueChange<FanState>>,
+    #[serde(rename = "fnsp")]
+    pub fan_speed: Option<ValueChange<FanSpeed>>,
+    #[serde(rename = "qtar")]
+    pub quality_target: Option<ValueChange<QualityTarget>>,
+    #[serde(rename = "oson")]
+    pub oscillation_status: Option<ValueChange<OscillationStatus>>,
+    #[serde(rename = "rhtm")]
+    pub air_quality_monitoring_status: Option<ValueChange<AirQualityMonitoringStatus>>,
+    #[serde(rename = "filf")]
+    #[serde(deserialize_with = "from_string_change")]
+    pub filter_life: Option<ValueChange<i32>>,
+    #[serde(rename = "ercd")]
+    pub ercd: Option<ValueChange<String>>,
+    #[serde(rename = "nmod")]
+    pub night_mode: Option<ValueChange<NightMode>>,
+    #[serde(rename = "wacd")]
+    pub wacd: Option<ValueChange<String>>,
+    #[serde(rename = "hmod")]
+    pub heat_mode: Option<ValueChange<HeatMode>>,
+    #[serde(rename = "hmax")]
+    #[serde(deserialize_with = "from_raw_string_change_to_kelvin")]
+    pub heat_target_kelvin: Option<ValueChange<f32>>,
+    #[serde(rename = "hsta")]
+    pub heat_state: Option<ValueChange<HeatState>>,
+    #[serde(rename = "ffoc")]
+    pub fan_focus_mode: Option<ValueChange<FanFocusMode>>,
+    #[serde(rename = "tilt")]
+    pub tilt_state: Option<ValueChange<TiltState>>,
+}
+
+impl ProductStateChange {
+    pub fn between(old: &ProductState, new: &ProductState) -> Self {
+        ProductStateChange {
+            fan_mode: change(&old.fan_mode, &new.fan_mode),
+            fan_state: change(&old.fan_state, &new.fan_state),
+            fan_speed: change(&old.fan_speed, &new.fan_speed),
+            quality_target: change(&old.quality_target, &new.quality_target),
+            oscillation_status: change(&old.oscillation_status, &new.oscillation_status),
+            air_quality_monitoring_status: change(&old.air_quality_monitoring_status, &new.air_quality_monitoring_status),
+            filter_life: change(&old.filter_life, &new.filter_life),
+            ercd: change(&old.ercd, &new.ercd),
+            night_mode: change(&old.night_mode, &new.night_mode),
+            wacd: change(&old.wacd, &new.wacd),
+            heat_mode: change(&old.heat_mode, &new.heat_mode),
+            heat_target_kelvin: change(&old.heat_target_kelvin, &new.heat_target_kelvin),
+            heat_state: change(&old.heat_state, &new.heat_state),
+            fan_focus_mode: change(&old.fan_focus_mode, &new.fan_focus_mode),
+            tilt_state: change(&old.tilt_state, &new.tilt_state),
+        }
+    }
+
+    pub fn apply_to(&self, state: &mut ProductState) {
+        apply(&self.fan_mode, &mut state.fan_mode);
+        apply(&self.fan_state, &mut state.fan_state);
+        apply(&self.fan_speed, &mut state.fan_speed);
+        apply(&self.quality_target, &mut state.quality_target);
+        apply(&self.oscillation_status, &mut state.oscillation_status);
+        apply(&self.air_quality_monitoring_status, &mut state.air_quality_monitoring_status);
+        apply(&self.filter_life, &mut state.filter_life);
+        apply(&self.ercd, &mut state.ercd);
+        apply(&self.night_mode, &mut state.night_mode);
+        apply(&self.wacd, &mut state.wacd);
+        apply(&self.heat_mode, &mut state.heat_mode);
+        apply(&self.heat_target_kelvin, &mut state.heat_target_kelvin);
+        apply(&self.heat_state, &mut state.heat_state);
+        apply(&self.fan_focus_mode, &mut state.fan_focus_mode);
+        apply(&self.tilt_state, &mut state.tilt_state);
+    }
+
+    pub fn changed_fields(&self) -> Vec<ProductStateField> {
+        let candidates = vec![
+            (ProductStateField::FanMode, is_changed(&self.fan_mode)),
+            (ProductStateField::FanState, is_changed(&self.fan_state)),
+            (ProductStateField::FanSpeed, is_changed(&self.fan_speed)),
+            (ProductStateField::QualityTarget, is_changed(&self.quality_target)),
+            (ProductStateField::OscillationStatus, is_changed(&self.oscillation_status)),
+            (ProductStateField::AirQualityMonitoringStatus, is_changed(&self.air_quality_monitoring_status)),
+            (ProductStateField::FilterLife, is_changed(&self.filter_life)),
+            (ProductStateField::ErrorCode, is_changed(&self.ercd)),
+            (ProductStateField::NightMode, is_changed(&self.night_mode)),
+            (ProductStateField::WarningCode, is_changed(&self.wacd)),
+            (ProductStateField::HeatMode, is_changed(&self.heat_mode)),
+            (ProductStateField::HeatTarget, is_changed(&self.heat_target_kelvin)),
+            (ProductStateField::HeatState, is_changed(&self.heat_state)),
+            (ProductStateField::FanFocusMode, is_changed(&self.fan_focus_mode)),
+            (ProductStateField::TiltState, is_changed(&self.tilt_state)),
+        ];
+
+        candidates.into_iter()
+            .filter(|(_, changed)| *changed)
+            .map(|(field, _)| field)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PurifierProductStateChange {
+    #[serde(rename = "fpwr")]
+    pub fan_power: Option<ValueChange<FanPower>>,
+    #[serde(rename = "fnst")]
+    pub fan_state: Option<ValueChange<FanState>>,
+    #[serde(rename = "fnsp")]
+    pub fan_speed: Option<ValueChange<FanSpeed>>,
+    #[serde(rename = "auto")]
+    pub auto_mode: Option<ValueChange<AutoMode>>,
+    #[serde(rename = "oscs")]
+    pub oscillation_state: Option<ValueChange<OscillationState>>,
+    #[serde(rename = "oson")]
+    pub oscillation_status: Option<ValueChange<OscillationStatus>>,
+    #[serde(rename = "osal", deserialize_with = "from_optional_string_change")]
+    pub oscillation_angle_low: Option<ValueChange<Option<u16>>>,
+    #[serde(rename = "osau", deserialize_with = "from_optional_string_change")]
+    pub oscillation_angle_high: Option<ValueChange<Option<u16>>>,
+    #[serde(rename = "ancp")]
+    pub oscillation_preset: Option<ValueChange<Option<String>>>,
+    #[serde(rename = "nmod")]
+    pub night_mode: Option<ValueChange<NightMode>>,
+    #[serde(rename = "rhtm")]
+    pub air_quality_monitoring_status: Option<ValueChange<AirQualityMonitoringStatus>>,
+    #[serde(rename = "fdir")]
+    pub front_airflow: Option<ValueChange<Option<FrontAirflow>>>,
+    #[serde(rename = "bril", deserialize_with = "from_optional_string_change")]
+    pub brightness: Option<ValueChange<Option<u16>>>,
+    #[serde(rename = "cflr", deserialize_with = "from_optional_string_change")]
+    pub carbon_filter_life: Option<ValueChange<Option<i32>>>,
+    #[serde(rename = "hflr", deserialize_with = "from_optional_string_change")]
+    pub hepa_filter_life: Option<ValueChange<Option<i32>>>,
+    #[serde(rename = "sltm", deserialize_with = "from_optional_string_change")]
+    pub sleep_timer: Option<ValueChange<Option<u32>>>,
+    #[serde(rename = "ercd")]
+    pub ercd: Option<ValueChange<String>>,
+    #[serde(rename = "wacd")]
+    pub wacd: Option<ValueChange<String>>,
+    #[serde(rename = "hmod")]
+    pub heat_mode: Option<ValueChange<Option<HeatMode>>>,
+    #[serde(rename = "hmax", deserialize_with = "from_optional_raw_string_change_to_kelvin")]
+    pub heat_target_kelvin: Option<ValueChange<Option<f32>>>,
+    #[serde(rename = "hsta")]
+    pub heat_state: Option<ValueChange<Option<HeatState>>>,
+    #[serde(rename = "tilt")]
+    pub tilt_state: Option<ValueChange<Option<TiltState>>>,
+    #[serde(rename = "hume")]
+    pub humidify_mode: Option<ValueChange<Option<HumidifyMode>>>,
+    #[serde(rename = "haut")]
+    pub auto_humidity: Option<ValueChange<Option<AutoHumidity>>>,
+    #[serde(rename = "humt", deserialize_with = "from_optional_string_change")]
+    pub humidity_target: Option<ValueChange<Option<u8>>>,
+    #[serde(rename = "rect", deserialize_with = "from_optional_string_change")]
+    pub auto_humidity_target: Option<ValueChange<Option<u8>>>,
+    #[serde(rename = "wath")]
+    pub water_hardness: Option<ValueChange<Option<WaterHardness>>>,
+    #[serde(rename = "clcr")]
+    pub clean_cycle: Option<ValueChange<Option<CleanCycle>>>,
+    #[serde(rename = "cltr", deserialize_with = "from_optional_string_change")]
+    pub time_until_clean_hours: Option<ValueChange<Option<u32>>>,
+    #[serde(rename = "cdrr", deserialize_with = "from_optional_string_change")]
+    pub clean_time_remaining_minutes: Option<ValueChange<Option<u32>>>,
+}
+
+impl PurifierProductStateChange {
+    pub fn between(old: &PurifierProductState, new: &PurifierProductState) -> Self {
+        PurifierProductStateChange {
+            fan_power: change(&old.fan_power, &new.fan_power),
+            fan_state: change(&old.fan_state, &new.fan_state),
+            fan_speed: change(&old.fan_speed, &new.fan_speed),
+            auto_mode: change(&old.auto_mode, &new.auto_mode),
+            oscillation_state: change(&old.oscillation_state, &new.oscillation_state),
+            oscillation_status: change(&old.oscillation_status, &new.oscillation_status),
+            oscillation_angle_low: change(&old.oscillation_angle_low, &new.oscillation_angle_low),
+            oscillation_angle_high: change(&old.oscillation_angle_high, &new.oscillation_angle_high),
+            oscillation_preset: change(&old.oscillation_preset, &new.oscillation_preset),
+            night_mode: change(&old.night_mode, &new.night_mode),
+            air_quality_monitoring_status: change(&old.air_quality_monitoring_status, &new.air_quality_monitoring_status),
+            front_airflow: change(&old.front_airflow, &new.front_airflow),
+            brightness: change(&old.brightness, &new.brightness),
+            carbon_filter_life: change(&old.carbon_filter_life, &new.carbon_filter_life),
+            hepa_filter_life: change(&old.hepa_filter_life, &new.hepa_filter_life),
+            sleep_timer: change(&old.sleep_timer, &new.sleep_timer),
+            ercd: change(&old.ercd, &new.ercd),
+            wacd: change(&old.wacd, &new.wacd),
+            heat_mode: change(&old.heat_mode, &new.heat_mode),
+            heat_target_kelvin: change(&old.heat_target_kelvin, &new.heat_target_kelvin),
+            heat_state: change(&old.heat_state, &new.heat_state),
+            tilt_state: change(&old.tilt_state, &new.tilt_state),
+            humidify_mode: change(&old.humidify_mode, &new.humidify_mode),
+            auto_humidity: change(&old.auto_humidity, &new.auto_humidity),
+            humidity_target: change(&old.humidity_target, &new.humidity_target),
+            auto_humidity_target: change(&old.auto_humidity_target, &new.auto_humidity_target),
+            water_hardness: change(&old.water_hardness, &new.water_hardness),
+            clean_cycle: change(&old.clean_cycle, &new.clean_cycle),
+            time_until_clean_hours: change(&old.time_until_clean_hours, &new.time_until_clean_hours),
+            clean_time_remaining_minutes: change(&old.clean_time_remaining_minutes, &new.clean_time_remaining_minutes),
+        }
+    }
+
+    pub fn apply_to(&self, state: &mut PurifierProductState) {
+        apply(&self.fan_power, &mut state.fan_power);
+        apply(&self.fan_state, &mut state.fan_state);
+        apply(&self.fan_speed, &mut state.fan_speed);
+        apply(&self.auto_mode, &mut state.auto_mode);
+        apply(&self.oscillation_state, &mut state.oscillation_state);
+        apply(&self.oscillation_status, &mut state.oscillation_status);
+        apply(&self.oscillation_angle_low, &mut state.oscillation_angle_low);
+        apply(&self.oscillation_angle_high, &mut state.oscillation_angle_high);
+        apply(&self.oscillation_preset, &mut state.oscillation_preset);
+        apply(&self.night_mode, &mut state.night_mode);
+        apply(&self.air_quality_monitoring_status, &mut state.air_quality_monitoring_status);
+        apply(&self.front_airflow, &mut state.front_airflow);
+        apply(&self.brightness, &mut state.brightness);
+        apply(&self.carbon_filter_life, &mut state.carbon_filter_life);
+        apply(&self.hepa_filter_life, &mut state.hepa_filter_life);
+        apply(&self.sleep_timer, &mut state.sleep_timer);
+        apply(&self.ercd, &mut state.ercd);
+        apply(&self.wacd, &mut state.wacd);
+        apply(&self.heat_mode, &mut state.heat_mode);
+        apply(&self.heat_target_kelvin, &mut state.heat_target_kelvin);
+        apply(&self.heat_state, &mut state.heat_state);
+        apply(&self.tilt_state, &mut state.tilt_state);
+        apply(&self.humidify_mode, &mut state.humidify_mode);
+        apply(&self.auto_humidity, &mut state.auto_humidity);
+        apply(&self.humidity_target, &mut state.humidity_target);
+        apply(&self.auto_humidity_target, &mut state.auto_humidity_target);
+        apply(&self.water_hardness, &mut state.water_hardness);
+        apply(&self.clean_cycle, &mut state.clean_cycle);
+        apply(&self.time_until_clean_hours, &mut state.time_until_clean_hours);
+        apply(&self.clean_time_remaining_minutes, &mut state.clean_time_remaining_minutes);
+    }
+
+    pub fn changed_fields(&self) -> Vec<ProductStateField> {
+        let candidates = vec![
+            (ProductStateField::FanPower, is_changed(&self.fan_power)),
+            (ProductStateField::FanState, is_changed(&self.fan_state)),
+            (ProductStateField::FanSpeed, is_changed(&self.fan_speed)),
+            (ProductStateField::AutoMode, is_changed(&self.auto_mode)),
+            (ProductStateField::OscillationState, is_changed(&self.oscillation_state)),
+            (ProductStateField::OscillationStatus, is_changed(&self.oscillation_status)),
+            (ProductStateField::OscillationAngleLow, is_changed(&self.oscillation_angle_low)),
+            (ProductStateField::OscillationAngleHigh, is_changed(&self.oscillation_angle_high)),
+            (ProductStateField::OscillationPreset, is_changed(&self.oscillation_preset)),
+            (ProductStateField::NightMode, is_changed(&self.night_mode)),
+            (ProductStateField::AirQualityMonitoringStatus, is_changed(&self.air_quality_monitoring_status)),
+            (ProductStateField::FrontAirflow, is_changed(&self.front_airflow)),
+            (ProductStateField::Brightness, is_changed(&self.brightness)),
+            (ProductStateField::CarbonFilterLife, is_changed(&self.carbon_filter_life)),
+            (ProductStateField::HepaFilterLife, is_changed(&self.hepa_filter_life)),
+            (ProductStateField::SleepTimer, is_changed(&self.sleep_timer)),
+            (ProductStateField::ErrorCode, is_changed(&self.ercd)),
+            (ProductStateField::WarningCode, is_changed(&self.wacd)),
+            (ProductStateField::HeatMode, is_changed(&self.heat_mode)),
+            (ProductStateField::HeatTarget, is_changed(&self.heat_target_kelvin)),
+            (ProductStateField::HeatState, is_changed(&self.heat_state)),
+            (ProductStateField::TiltState, is_changed(&self.tilt_state)),
+            (ProductStateField::HumidifyMode, is_changed(&self.humidify_mode)),
+            (ProductStateField::AutoHumidity, is_changed(&self.auto_humidity)),
+            (ProductStateField::HumidityTarget, is_changed(&self.humidity_target)),
+            (ProductStateField::AutoHumidityTarget, is_changed(&self.auto_humidity_target)),
+            (ProductStateField::WaterHardness, is_changed(&self.water_hardness)),
+            (ProductStateField::CleanCycle, is_changed(&self.clean_cycle)),
+            (ProductStateField::TimeUntilClean, is_changed(&self.time_until_clean_hours)),
+            (ProductStateField::CleanTimeRemaining, is_changed(&self.clean_time_remaining_minutes)),
+        ];
+
+        candidates.into_iter()
+            .filter(|(_, changed)| *changed)
+            .map(|(field, _)| field)
+            .collect()
+    }
+}
+
+fn change<T: Clone>(old: &T, new: &T) -> Option<ValueChange<T>> {
+    Some(ValueChange { old: old.clone(), new: new.clone() })
+}
+
+fn apply<T: Clone>(change: &Option<ValueChange<T>>, value: &mut T) {
+    if let Some(change) = change {
+        *value = change.new.clone();
+    }
+}
+
+fn is_changed<T: PartialEq>(change: &Option<ValueChange<T>>) -> bool {
+    match change {
+        Some(change) => change.is_changed(),
+        None => false
+    }
+}
+
 fn from_string<'de, T, D>(deserializer: D) -> Result<T, D::Error>
     where T: std::str::FromStr,
           T::Err: std::fmt::Display,
@@ -155,16 +837,95 @@ fn from_raw_string_to_kelvin<'de, T, D>(deserializer: D) -> Result<T, D::Error>
     let s = String::deserialize(deserializer)?;
     T::from_str(&s)
         .map_err(serde::de::Error::custom)
-        .map(|x| x / T::from_f32(10.0).unwrap())
+        .map(from_deci_kelvin)
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+/// Reads numeric strings, treating markers like `OFF`, `INV` or `INIT` as no value.
+fn from_optional_string<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+    where T: std::str::FromStr,
+          D: Deserializer<'de>
+{
+    let s = String::deserialize(deserializer)?;
+    Ok(T::from_str(&s).ok())
+}
+
+fn from_optional_raw_string_to_kelvin<'de, D>(deserializer: D) -> Result<Option<f32>, D::Error>
+    where D: Deserializer<'de>
+{
+    let value: Option<f32> = from_optional_string(deserializer)?;
+    Ok(value.map(from_deci_kelvin))
+}
+
+fn from_string_change<'de, T, D>(deserializer: D) -> Result<Option<ValueChange<T>>, D::Error>
+    where T: std::str::FromStr,
+          T::Err: std::fmt::Display,
+          D: Deserializer<'de>
+{
+    let change = ValueChange::<String>::deserialize(deserializer)?;
+
+    Ok(Some(ValueChange {
+        old: T::from_str(&change.old).map_err(serde::de::Error::custom)?,
+        new: T::from_str(&change.new).map_err(serde::de::Error::custom)?,
+    }))
+}
+
+fn from_raw_string_change_to_kelvin<'de, T, D>(deserializer: D) -> Result<Option<ValueChange<T>>, D::Error>
+    where T: std::str::FromStr + std::ops::Div + num_traits::Float + num_traits::FromPrimitive,
+          T::Err: std::fmt::Display,
+          D: Deserializer<'de>
+{
+    let change = from_string_change::<T, D>(deserializer)?;
+
+    Ok(change.map(|change| ValueChange {
+        old: from_deci_kelvin(change.old),
+        new: from_deci_kelvin(change.new),
+    }))
+}
+
+fn from_optional_string_change<'de, T, D>(deserializer: D) -> Result<Option<ValueChange<Option<T>>>, D::Error>
+    where T: std::str::FromStr,
+          D: Deserializer<'de>
+{
+    let change = ValueChange::<String>::deserialize(deserializer)?;
+
+    Ok(Some(ValueChange {
+        old: T::from_str(&change.old).ok(),
+        new: T::from_str(&change.new).ok(),
+    }))
+}
+
+fn from_optional_raw_string_change_to_kelvin<'de, D>(deserializer: D) -> Result<Option<ValueChange<Option<f32>>>, D::Error>
+    where D: Deserializer<'de>
+{
+    let change = from_optional_string_change::<f32, D>(deserializer)?;
+
+    Ok(change.map(|change| ValueChange {
+        old: change.old.map(from_deci_kelvin),
+        new: change.new.map(from_deci_kelvin),
+    }))
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 #[serde(tag = "msg")]
 pub enum StatusCurrentResponse {
     #[serde(rename = "ENVIRONMENTAL-CURRENT-SENSOR-DATA")]
     EnvironmentalCurrentSensorData(EnvironmentalCurrentSensorRaw),
     #[serde(rename = "CURRENT-STATE")]
-    CurrentState(CurrentStateRaw)
+    CurrentState(CurrentStateRaw),
+    #[serde(rename = "STATE-CHANGE")]
+    StateChange(StateChangeRaw)
+}
+
+/// Status messages of the purifier generation, see `ProductGeneration`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "msg")]
+pub enum PurifierStatusCurrentResponse {
+    #[serde(rename = "ENVIRONMENTAL-CURRENT-SENSOR-DATA")]
+    EnvironmentalCurrentSensorData(PurifierEnvironmentalSensorRaw),
+    #[serde(rename = "CURRENT-STATE")]
+    CurrentState(PurifierCurrentStateRaw),
+    #[serde(rename = "STATE-CHANGE")]
+    StateChange(PurifierStateChangeRaw)
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -195,11 +956,37 @@ pub struct HelloRaw {
     pub reset_source: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GoodbyeRaw {
+    pub time: String,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 #[serde(tag = "msg")]
+#[allow(clippy::large_enum_variant)]
 pub enum StatusConnectionResponse {
     #[serde(rename = "HELLO")]
     Hello(HelloRaw),
+    #[serde(rename = "GOODBYE")]
+    Goodbye(GoodbyeRaw),
+}
+
+impl StatusConnectionResponse {
+    pub fn time(&self) -> &str {
+        match self {
+            StatusConnectionResponse::Hello(hello) => &hello.time,
+            StatusConnectionResponse::Goodbye(goodbye) => &goodbye.time,
+        }
+    }
+
+    pub fn is_online(&self) -> bool {
+        match self {
+            StatusConnectionResponse::Hello(_) => true,
+            StatusConnectionResponse::Goodbye(_) => false,
+        }
+    }
 }
 
 
@@ -319,16 +1106,58 @@ mod test {
         let environment_current_sensor_data_raw: EnvironmentalCurrentSensorDataRaw = serde_json::from_str(raw).unwrap();
 
         let expected = EnvironmentCurrentSensorData{
-            humidity_percentage: 73.0,
+            humidity_percentage: Some(73.0),
             dust: 2.0,
-            sleep_timer: 0.0,
-            temperature_kelvin: 299.0,
+            sleep_timer: None,
+            temperature: Some(Temperature::from_kelvin(299.0)),
             volatile_organic_compounds_ppm: 2.0
         };
 
         let actual = EnvironmentCurrentSensorData::from_raw(&environment_current_sensor_data_raw);
 
         assert_eq!(actual, expected);
+
+        let warming_up = EnvironmentalCurrentSensorDataRaw { tact: String::from("INIT"), ..environment_current_sensor_data_raw };
+        assert_eq!(EnvironmentCurrentSensorData::from_raw(&warming_up).temperature, None);
+    }
+
+    #[test]
+    fn it_converts_purifier_environment_sensor_data_from_raw() {
+        let raw = r#"
+            {
+                "tact":"2977",
+                "hact":"0040",
+                "pm25":"0012",
+                "pm10":"0008",
+                "p25r":"0013",
+                "p10r":"0009",
+                "va10":"INIT",
+                "noxl":"0015",
+                "hcho":"0004",
+                "hchr":"0003",
+                "sltm":"0120"
+            }"#;
+
+        let raw: PurifierEnvironmentalSensorDataRaw = serde_json::from_str(raw).unwrap();
+
+        let expected = PurifierEnvironmentCurrentSensorData {
+            temperature: Some(Temperature::from_kelvin(297.7)),
+            humidity_percentage: Some(40.0),
+            pm25: Some(12.0),
+            pm10: Some(8.0),
+            pm25_raw: Some(13.0),
+            pm10_raw: Some(9.0),
+            volatile_organic_compounds_index: None,
+            nitrogen_dioxide_index: Some(1.5),
+            formaldehyde_mg_per_m3: Some(0.004),
+            formaldehyde_raw_mg_per_m3: Some(0.003),
+            carbon_dioxide_ppm: None,
+            sleep_timer: Some(Duration::from_secs(120 * 60)),
+        };
+
+        let actual = PurifierEnvironmentCurrentSensorData::from_raw(&raw);
+
+        assert_eq!(actual, expected);
     }
 
     #[test]
@@ -399,4 +1228,85 @@ mod test {
 
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn it_converts_state_change_from_raw() {
+        let raw = r#"
+            {
+                "msg": "STATE-CHANGE",
+                "time": "2020-06-09T14:10:21.000Z",
+                "mode-reason": "RAPP",
+                "state-reason": "MODE",
+                "product-state": {
+                    "fmod": ["AUTO", "FAN"],
+                    "fnst": ["FAN", "FAN"],
+                    "fnsp": ["AUTO", "0004"],
+                    "qtar": ["0001", "0001"],
+                    "oson": ["ON", "ON"],
+                    "rhtm": ["ON", "ON"],
+                    "filf": ["3297", "3297"],
+                    "ercd": ["NONE", "NONE"],
+                    "nmod": ["ON", "OFF"],
+                    "wacd": ["NONE", "NONE"],
+                    "hmod": ["OFF", "OFF"],
+                    "hmax": ["2982", "2962"],
+                    "hsta": ["OFF", "OFF"],
+                    "ffoc": ["OFF", "OFF"],
+                    "tilt": ["OK", "OK"]
+                },
+                "scheduler": {
+                    "srsc": "e854",
+                    "dstv": "0000",
+                    "tzid": "0001"
+                }
+            }"#;
+
+        let actual: StatusCurrentResponse = serde_json::from_str(raw).unwrap();
+
+        let state_change = match actual {
+            StatusCurrentResponse::StateChange(state_change) => state_change,
+            other => panic!("unexpected message {:?}", other)
+        };
+
+        assert_eq!(state_change.mode_reason, "RAPP");
+        assert_eq!(state_change.product_state.fan_speed, Some(ValueChange{ old: FanSpeed::Auto, new: FanSpeed::Speed_4 }));
+        assert_eq!(state_change.product_state.filter_life, Some(ValueChange{ old: 3297, new: 3297 }));
+        assert_eq!(state_change.product_state.heat_target_kelvin, Some(ValueChange{ old: 298.2, new: 296.2 }));
+        assert_eq!(state_change.product_state.changed_fields(), vec![
+            ProductStateField::FanMode,
+            ProductStateField::FanSpeed,
+            ProductStateField::NightMode,
+            ProductStateField::HeatTarget,
+        ]);
+    }
+
+    #[test]
+    fn it_converts_partial_state_change_from_raw() {
+        let raw = r#"
+            {
+                "msg": "STATE-CHANGE",
+                "time": "2020-06-09T14:10:21.000Z",
+                "mode-reason": "LAPP",
+                "state-reason": "MODE",
+                "product-state": {
+                    "oson": ["ON", "OFF"]
+                }
+            }"#;
+
+        let actual: StateChangeRaw = serde_json::from_str(raw).unwrap();
+
+        let expected = StateChangeRaw{
+            time: String::from("2020-06-09T14:10:21.000Z"),
+            mode_reason: String::from("LAPP"),
+            state_reason: String::from("MODE"),
+            product_state: ProductStateChange{
+                oscillation_status: Some(ValueChange{ old: OscillationStatus::On, new: OscillationStatus::Off }),
+                ..Default::default()
+            },
+            scheduler: None
+        };
+
+        assert_eq!(actual, expected);
+        assert_eq!(actual.product_state.changed_fields(), vec![ProductStateField::OscillationStatus]);
+    }
 }
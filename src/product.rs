@@ -0,0 +1,184 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::api_error::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ProductType {
+    PureCoolLink,
+    PureCoolLinkDesk,
+    PureHotCoolLink,
+    PureCool,
+    PureCoolM,
+    PureCoolDesk,
+    PurifierCool,
+    PurifierCoolFormaldehyde,
+    PureHotCool,
+    PureHotCoolM,
+    PurifierHotCool,
+    PurifierHotCoolFormaldehyde,
+    PureHumidifyCool,
+    PurifierHumidifyCool,
+    PurifierHumidifyCoolFormaldehyde,
+    BigQuiet,
+    Robot360Eye
+}
+
+/// Decides which CURRENT-STATE model a device reports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ProductGeneration {
+    /// Pure Cool Link family: `fmod`, `qtar`, `ffoc`, filter life in hours.
+    Link,
+    /// TP04/DP04/HP04 and newer: `fpwr`, `auto`, oscillation angles, filter life in percent.
+    Purifier,
+    Robot
+}
+
+/// Features that decide which settings and sensor fields apply to a device.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct Capabilities {
+    pub heating: bool,
+    pub humidifier: bool,
+    pub oscillation_angles: bool,
+    pub formaldehyde_sensor: bool,
+    pub no2_sensor: bool,
+    pub carbon_filter: bool
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProductInfo {
+    pub product_type: ProductType,
+    pub code: &'static str,
+    pub name: &'static str,
+    pub generation: ProductGeneration,
+    pub capabilities: Capabilities
+}
+
+const fn capabilities(heating: bool,
+                      humidifier: bool,
+                      oscillation_angles: bool,
+                      formaldehyde_sensor: bool,
+                      no2_sensor: bool,
+                      carbon_filter: bool) -> Capabilities {
+    Capabilities { heating, humidifier, oscillation_angles, formaldehyde_sensor, no2_sensor, carbon_filter }
+}
+
+const LINK: Capabilities = capabilities(false, false, false, false, false, false);
+const HOT_LINK: Capabilities = capabilities(true, false, false, false, false, false);
+const PURIFIER: Capabilities = capabilities(false, false, true, false, true, true);
+const PURIFIER_FORMALDEHYDE: Capabilities = capabilities(false, false, true, true, true, true);
+const HOT_PURIFIER: Capabilities = capabilities(true, false, true, false, true, true);
+const HOT_PURIFIER_FORMALDEHYDE: Capabilities = capabilities(true, false, true, true, true, true);
+const HUMIDIFIER: Capabilities = capabilities(false, true, true, false, true, true);
+const HUMIDIFIER_FORMALDEHYDE: Capabilities = capabilities(false, true, true, true, true, true);
+const BIG_QUIET: Capabilities = capabilities(false, false, false, true, true, true);
+const ROBOT: Capabilities = capabilities(false, false, false, false, false, false);
+
+pub const PRODUCTS: [ProductInfo; 17] = [
+    ProductInfo { product_type: ProductType::PureCoolLink, code: "475", name: "Pure Cool Link (TP02)", generation: ProductGeneration::Link, capabilities: LINK },
+    ProductInfo { product_type: ProductType::PureCoolLinkDesk, code: "469", name: "Pure Cool Link Desk (DP01)", generation: ProductGeneration::Link, capabilities: LINK },
+    ProductInfo { product_type: ProductType::PureHotCoolLink, code: "455", name: "Pure Hot+Cool Link (HP02)", generation: ProductGeneration::Link, capabilities: HOT_LINK },
+    ProductInfo { product_type: ProductType::PureCool, code: "438", name: "Pure Cool (TP04)", generation: ProductGeneration::Purifier, capabilities: PURIFIER },
+    ProductInfo { product_type: ProductType::PureCoolM, code: "438M", name: "Pure Cool (TP04, M variant)", generation: ProductGeneration::Purifier, capabilities: PURIFIER },
+    ProductInfo { product_type: ProductType::PureCoolDesk, code: "520", name: "Pure Cool Desk (DP04)", generation: ProductGeneration::Purifier, capabilities: PURIFIER },
+    ProductInfo { product_type: ProductType::PurifierCool, code: "438E", name: "Purifier Cool (TP07)", generation: ProductGeneration::Purifier, capabilities: PURIFIER },
+    ProductInfo { product_type: ProductType::PurifierCoolFormaldehyde, code: "438K", name: "Purifier Cool Formaldehyde (TP09)", generation: ProductGeneration::Purifier, capabilities: PURIFIER_FORMALDEHYDE },
+    ProductInfo { product_type: ProductType::PureHotCool, code: "527", name: "Pure Hot+Cool (HP04)", generation: ProductGeneration::Purifier, capabilities: HOT_PURIFIER },
+    ProductInfo { product_type: ProductType::PureHotCoolM, code: "527M", name: "Pure Hot+Cool (HP04, M variant)", generation: ProductGeneration::Purifier, capabilities: HOT_PURIFIER },
+    ProductInfo { product_type: ProductType::PurifierHotCool, code: "527E", name: "Purifier Hot+Cool (HP07)", generation: ProductGeneration::Purifier, capabilities: HOT_PURIFIER },
+    ProductInfo { product_type: ProductType::PurifierHotCoolFormaldehyde, code: "527K", name: "Purifier Hot+Cool Formaldehyde (HP09)", generation: ProductGeneration::Purifier, capabilities: HOT_PURIFIER_FORMALDEHYDE },
+    ProductInfo { product_type: ProductType::PureHumidifyCool, code: "358", name: "Pure Humidify+Cool (PH01)", generation: ProductGeneration::Purifier, capabilities: HUMIDIFIER },
+    ProductInfo { product_type: ProductType::PurifierHumidifyCool, code: "358E", name: "Purifier Humidify+Cool (PH03)", generation: ProductGeneration::Purifier, capabilities: HUMIDIFIER },
+    ProductInfo { product_type: ProductType::PurifierHumidifyCoolFormaldehyde, code: "358K", name: "Purifier Humidify+Cool Formaldehyde (PH04)", generation: ProductGeneration::Purifier, capabilities: HUMIDIFIER_FORMALDEHYDE },
+    ProductInfo { product_type: ProductType::BigQuiet, code: "664", name: "Purifier Big+Quiet Formaldehyde (BP03)", generation: ProductGeneration::Purifier, capabilities: BIG_QUIET },
+    ProductInfo { product_type: ProductType::Robot360Eye, code: "N223", name: "360 Eye", generation: ProductGeneration::Robot, capabilities: ROBOT },
+];
+
+impl ProductType {
+    /// Looks up the product code used in manifests and MQTT topics, e.g. "455".
+    pub fn from_code(code: &str) -> Option<Self> {
+        PRODUCTS.iter()
+            .find(|product| product.code.eq_ignore_ascii_case(code))
+            .map(|product| product.product_type)
+    }
+
+    pub fn info(self) -> &'static ProductInfo {
+        PRODUCTS.iter()
+            .find(|product| product.product_type == self)
+            .unwrap()
+    }
+
+    pub fn code(self) -> &'static str {
+        self.info().code
+    }
+
+    pub fn name(self) -> &'static str {
+        self.info().name
+    }
+
+    pub fn generation(self) -> ProductGeneration {
+        self.info().generation
+    }
+
+    pub fn capabilities(self) -> Capabilities {
+        self.info().capabilities
+    }
+}
+
+impl std::fmt::Display for ProductType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for ProductType {
+    type Err = DysonError;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        Self::from_code(code).ok_or_else(|| DysonError::UnknownProductType(String::from(code)))
+    }
+}
+
+impl Serialize for ProductType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.code())
+    }
+}
+
+impl<'de> Deserialize<'de> for ProductType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = String::deserialize(deserializer)?;
+
+        code.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_maps_product_codes_both_ways() {
+        for product in PRODUCTS.iter() {
+            assert_eq!(ProductType::from_code(product.code), Some(product.product_type));
+            assert_eq!(product.product_type.code(), product.code);
+        }
+
+        assert_eq!("438k".parse::<ProductType>().unwrap(), ProductType::PurifierCoolFormaldehyde);
+        assert_eq!(ProductType::from_code("438M"), Some(ProductType::PureCoolM));
+        assert_eq!(ProductType::PureHotCoolM.generation(), ProductGeneration::Purifier);
+        assert!(matches!("999".parse::<ProductType>(), Err(DysonError::UnknownProductType(_))));
+    }
+
+    #[test]
+    fn it_knows_product_capabilities() {
+        assert!(ProductType::PureHotCoolLink.capabilities().heating);
+        assert!(!ProductType::PureHotCoolLink.capabilities().oscillation_angles);
+        assert!(ProductType::PureHumidifyCool.capabilities().humidifier);
+        assert!(ProductType::PurifierHotCoolFormaldehyde.capabilities().formaldehyde_sensor);
+        assert!(!ProductType::PureCoolLink.capabilities().no2_sensor);
+        assert_eq!(ProductType::PureHotCoolLink.generation(), ProductGeneration::Link);
+        assert_eq!(ProductType::PureHotCool.generation(), ProductGeneration::Purifier);
+    }
+}
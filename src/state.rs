@@ -0,0 +1,219 @@
+use std::time::Duration;
+
+use super::aqi::*;
+use super::comfort::*;
+use super::control::*;
+use super::mqtt::*;
+use super::units::*;
+
+/// Generation independent view of a device's product state.
+///
+/// Fields a generation doesn't report are `None`: Link devices report filter life in
+/// hours, while the purifier generation reports HEPA and carbon filter life in percent.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceState {
+    pub power: bool,
+    pub auto_mode: bool,
+    pub fan_running: bool,
+    pub fan_speed: FanSpeed,
+    pub oscillation: bool,
+    pub oscillation_angles: Option<(u16, u16)>,
+    pub night_mode: bool,
+    pub air_quality_monitoring: bool,
+    pub front_airflow: Option<bool>,
+    pub filter_life_hours: Option<i32>,
+    pub hepa_filter_life_percent: Option<i32>,
+    pub carbon_filter_life_percent: Option<i32>,
+    pub sleep_timer: Option<Duration>,
+    pub heating: Option<bool>,
+    pub heat_target: Option<Temperature>,
+    pub humidification: Option<bool>,
+    pub auto_humidity: Option<bool>,
+    pub humidity_target_percent: Option<u8>,
+    pub water_hardness: Option<WaterHardness>,
+    pub clean_cycle: Option<CleanCycle>,
+    pub hours_until_clean: Option<u32>,
+    pub clean_time_remaining_minutes: Option<u32>,
+    pub warnings: Vec<DeviceWarning>,
+    pub error_code: String,
+    pub warning_code: String
+}
+
+pub const WARNING_CODE_NONE: &str = "NONE";
+pub const WARNING_CODE_EMPTY_WATER_TANK: &str = "TNKE";
+
+/// Warnings a device needs attention for, decoded from `wacd` and the humidifier state.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeviceWarning {
+    EmptyWaterTank,
+    CleanDue,
+    Other(String)
+}
+
+impl DeviceWarning {
+    /// `None` for the code a device reports when there is nothing to warn about.
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "" | WARNING_CODE_NONE => None,
+            WARNING_CODE_EMPTY_WATER_TANK => Some(DeviceWarning::EmptyWaterTank),
+            other => Some(DeviceWarning::Other(String::from(other)))
+        }
+    }
+}
+
+impl From<&ProductState> for DeviceState {
+    fn from(state: &ProductState) -> Self {
+        DeviceState {
+            power: state.fan_mode != FanMode::Off,
+            auto_mode: state.fan_mode == FanMode::Auto,
+            fan_running: state.fan_state == FanState::On,
+            fan_speed: state.fan_speed.clone(),
+            oscillation: state.oscillation_status == OscillationStatus::On,
+            oscillation_angles: None,
+            night_mode: state.night_mode == NightMode::On,
+            air_quality_monitoring: state.air_quality_monitoring_status == AirQualityMonitoringStatus::On,
+            front_airflow: None,
+            filter_life_hours: Some(state.filter_life),
+            hepa_filter_life_percent: None,
+            carbon_filter_life_percent: None,
+            sleep_timer: None,
+            heating: Some(state.heat_mode == HeatMode::On),
+            heat_target: Some(Temperature::from_kelvin(state.heat_target_kelvin)),
+            humidification: None,
+            auto_humidity: None,
+            humidity_target_percent: None,
+            water_hardness: None,
+            clean_cycle: None,
+            hours_until_clean: None,
+            clean_time_remaining_minutes: None,
+            warnings: DeviceWarning::from_code(&state.wacd).into_iter().collect(),
+            error_code: state.ercd.clone(),
+            warning_code: state.wacd.clone()
+        }
+    }
+}
+
+impl From<&PurifierProductState> for DeviceState {
+    fn from(state: &PurifierProductState) -> Self {
+        let oscillation_angles = match (state.oscillation_angle_low, state.oscillation_angle_high) {
+            (Some(low), Some(high)) => Some((low, high)),
+            _ => None
+        };
+
+        let mut warnings: Vec<DeviceWarning> = DeviceWarning::from_code(&state.wacd).into_iter().collect();
+        if state.time_until_clean_hours == Some(0) && state.clean_cycle != Some(CleanCycle::Active) {
+            warnings.push(DeviceWarning::CleanDue);
+        }
+
+        DeviceState {
+            power: state.fan_power == FanPower::On,
+            auto_mode: state.auto_mode == AutoMode::On,
+            fan_running: state.fan_state == FanState::On,
+            fan_speed: state.fan_speed.clone(),
+            oscillation: state.oscillation_status == OscillationStatus::On,
+            oscillation_angles,
+            night_mode: state.night_mode == NightMode::On,
+            air_quality_monitoring: state.air_quality_monitoring_status == AirQualityMonitoringStatus::On,
+            front_airflow: state.front_airflow.as_ref().map(|front_airflow| *front_airflow == FrontAirflow::On),
+            filter_life_hours: None,
+            hepa_filter_life_percent: state.hepa_filter_life,
+            carbon_filter_life_percent: state.carbon_filter_life,
+            sleep_timer: state.sleep_timer
+                .filter(|minutes| *minutes > 0)
+                .map(|minutes| Duration::from_secs(u64::from(minutes) * 60)),
+            heating: state.heat_mode.as_ref().map(|heat_mode| *heat_mode == HeatMode::On),
+            heat_target: state.heat_target_kelvin.map(Temperature::from_kelvin),
+            humidification: state.humidify_mode.as_ref().map(|humidify_mode| *humidify_mode == HumidifyMode::On),
+            auto_humidity: state.auto_humidity.as_ref().map(|auto_humidity| *auto_humidity == AutoHumidity::On),
+            humidity_target_percent: state.humidity_target,
+            water_hardness: state.water_hardness.clone(),
+            clean_cycle: state.clean_cycle.clone(),
+            hours_until_clean: state.time_until_clean_hours,
+            clean_time_remaining_minutes: state.clean_time_remaining_minutes,
+            warnings,
+            error_code: state.ercd.clone(),
+            warning_code: state.wacd.clone()
+        }
+    }
+}
+
+/// Generation independent view of the environmental sensors.
+///
+/// The Link generation reports dust as a level without PM2.5/PM10 split, and its VOC level
+/// is mapped to `volatile_organic_compounds_index`.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct SensorReading {
+    pub temperature: Option<Temperature>,
+    pub humidity_percentage: Option<f32>,
+    pub dust: Option<f32>,
+    pub pm25: Option<f32>,
+    pub pm10: Option<f32>,
+    pub volatile_organic_compounds_index: Option<f32>,
+    pub nitrogen_dioxide_index: Option<f32>,
+    pub formaldehyde_mg_per_m3: Option<f32>,
+    pub carbon_dioxide_ppm: Option<f32>,
+    pub sleep_timer: Option<Duration>
+}
+
+impl SensorReading {
+    /// Uses the PM2.5/PM10 readings of the purifier generation; the Link dust level has no unit.
+    pub fn air_quality_index(&self, standard: AqiStandard) -> Option<AirQualityIndex> {
+        standard.calculate(self.pm25, self.pm10)
+    }
+
+    pub fn dew_point_kelvin(&self) -> Option<f32> {
+        let (temperature_kelvin, humidity_percentage) = self.climate()?;
+        dew_point_kelvin(temperature_kelvin, humidity_percentage)
+    }
+
+    pub fn absolute_humidity(&self) -> Option<f32> {
+        self.climate().map(|(temperature_kelvin, humidity_percentage)| absolute_humidity(temperature_kelvin, humidity_percentage))
+    }
+
+    pub fn heat_index_kelvin(&self) -> Option<f32> {
+        self.climate().map(|(temperature_kelvin, humidity_percentage)| heat_index_kelvin(temperature_kelvin, humidity_percentage))
+    }
+
+    pub fn humidex(&self) -> Option<f32> {
+        let (temperature_kelvin, humidity_percentage) = self.climate()?;
+        humidex(temperature_kelvin, humidity_percentage)
+    }
+
+    pub fn comfort(&self) -> Option<Comfort> {
+        self.humidity_percentage.map(Comfort::from_humidity)
+    }
+
+    fn climate(&self) -> Option<(f32, f32)> {
+        Some((self.temperature?.kelvin(), self.humidity_percentage?))
+    }
+}
+
+impl From<&EnvironmentalCurrentSensorDataRaw> for SensorReading {
+    fn from(raw: &EnvironmentalCurrentSensorDataRaw) -> Self {
+        SensorReading {
+            temperature: parse_deci_kelvin(&raw.tact).map(Temperature::from_kelvin),
+            humidity_percentage: parse_sensor(Some(&raw.hact), 1.0),
+            dust: parse_sensor(Some(&raw.pact), 1.0),
+            volatile_organic_compounds_index: parse_sensor(Some(&raw.vact), 1.0),
+            sleep_timer: parse_sleep_timer(&raw.sltm),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&PurifierEnvironmentCurrentSensorData> for SensorReading {
+    fn from(data: &PurifierEnvironmentCurrentSensorData) -> Self {
+        SensorReading {
+            temperature: data.temperature,
+            humidity_percentage: data.humidity_percentage,
+            dust: None,
+            pm25: data.pm25,
+            pm10: data.pm10,
+            volatile_organic_compounds_index: data.volatile_organic_compounds_index,
+            nitrogen_dioxide_index: data.nitrogen_dioxide_index,
+            formaldehyde_mg_per_m3: data.formaldehyde_mg_per_m3,
+            carbon_dioxide_ppm: data.carbon_dioxide_ppm,
+            sleep_timer: data.sleep_timer
+        }
+    }
+}
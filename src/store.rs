@@ -0,0 +1,518 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, Utc};
+use tokio::sync::broadcast;
+
+use super::device::*;
+use super::mqtt::*;
+use super::state::*;
+
+const UPDATE_BUFFER_SIZE: usize = 64;
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct DeviceSnapshot {
+    pub product_state: Option<ProductState>,
+    pub purifier_state: Option<PurifierProductState>,
+    pub environment: Option<EnvironmentCurrentSensorData>,
+    pub purifier_environment: Option<PurifierEnvironmentCurrentSensorData>,
+    pub sensor_reading: Option<SensorReading>,
+    pub connection: Option<StatusConnectionResponse>,
+    pub product_state_updated: HashMap<ProductStateField, DateTime<Utc>>,
+    /// STATE-CHANGE messages received before the first CURRENT-STATE, replayed onto it when newer.
+    pub pending_changes: Vec<(ProductStateChange, DateTime<Utc>)>,
+    pub pending_purifier_changes: Vec<(PurifierProductStateChange, DateTime<Utc>)>,
+    pub environment_updated: Option<DateTime<Utc>>,
+    pub connection_updated: Option<DateTime<Utc>>,
+}
+
+impl DeviceSnapshot {
+    /// Whether the device last announced itself with HELLO (online) or GOODBYE (offline).
+    pub fn is_online(&self) -> Option<bool> {
+        self.connection.as_ref().map(|connection| connection.is_online())
+    }
+
+    pub fn device_state(&self) -> Option<DeviceState> {
+        match (&self.product_state, &self.purifier_state) {
+            (Some(product_state), _) => {
+                let mut state = DeviceState::from(product_state);
+                state.sleep_timer = self.environment.as_ref().and_then(|environment| environment.sleep_timer);
+                Some(state)
+            },
+            (None, Some(purifier_state)) => Some(DeviceState::from(purifier_state)),
+            (None, None) => None
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeviceStateUpdate {
+    ProductState {
+        serial: String,
+        fields: Vec<ProductStateField>
+    },
+    Environment {
+        serial: String
+    },
+    Connection {
+        serial: String
+    }
+}
+
+pub struct DeviceStateStore {
+    devices: RwLock<HashMap<String, DeviceSnapshot>>,
+    sender: broadcast::Sender<DeviceStateUpdate>
+}
+
+impl Default for DeviceStateStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeviceStateStore {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(UPDATE_BUFFER_SIZE);
+
+        DeviceStateStore {
+            devices: RwLock::new(HashMap::new()),
+            sender
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<DeviceStateUpdate> {
+        self.sender.subscribe()
+    }
+
+    pub fn snapshot(&self, serial: &str) -> Option<DeviceSnapshot> {
+        self.devices.read().unwrap().get(serial).cloned()
+    }
+
+    pub fn product_state(&self, serial: &str) -> Option<ProductState> {
+        self.snapshot(serial).and_then(|snapshot| snapshot.product_state)
+    }
+
+    pub fn device_state(&self, serial: &str) -> Option<DeviceState> {
+        self.snapshot(serial).and_then(|snapshot| snapshot.device_state())
+    }
+
+    pub fn sensor_reading(&self, serial: &str) -> Option<SensorReading> {
+        self.snapshot(serial).and_then(|snapshot| snapshot.sensor_reading)
+    }
+
+    pub fn environment(&self, serial: &str) -> Option<EnvironmentCurrentSensorData> {
+        self.snapshot(serial).and_then(|snapshot| snapshot.environment)
+    }
+
+    pub fn serials(&self) -> Vec<String> {
+        self.devices.read().unwrap().keys().cloned().collect()
+    }
+
+    pub fn apply(&self, serial: &str, message: &DeviceMessage) -> Option<DeviceStateUpdate> {
+        let update = {
+            let mut devices = self.devices.write().unwrap();
+            let snapshot = devices.entry(String::from(serial)).or_default();
+
+            apply_message(serial, snapshot, message)
+        };
+
+        if let Some(update) = &update {
+            // an error only means nobody is subscribed at the moment
+            let _ = self.sender.send(update.clone());
+        }
+
+        update
+    }
+
+    pub fn track(store: Arc<DeviceStateStore>, device: &LocalDevice) {
+        let serial = device.serial.clone();
+        let mut receiver = device.subscribe();
+
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(message) => { store.apply(&serial, &message); },
+                    Err(broadcast::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::RecvError::Closed) => break
+                }
+            }
+        });
+    }
+}
+
+fn apply_message(serial: &str, snapshot: &mut DeviceSnapshot, message: &DeviceMessage) -> Option<DeviceStateUpdate> {
+    match message {
+        DeviceMessage::StatusCurrent(StatusCurrentResponse::CurrentState(current_state)) => {
+            let updated = parse_time(&current_state.time);
+            let mut product_state = current_state.product_state.clone();
+            for (change, changed) in snapshot.pending_changes.drain(..) {
+                if changed > updated {
+                    change.apply_to(&mut product_state);
+                }
+            }
+
+            let fields = match &snapshot.product_state {
+                Some(old) => ProductStateChange::between(old, &product_state).changed_fields(),
+                None => ProductState::FIELDS.to_vec()
+            };
+
+            snapshot.product_state = Some(product_state);
+            stamp(snapshot, &fields, updated);
+
+            Some(DeviceStateUpdate::ProductState { serial: String::from(serial), fields })
+        },
+        DeviceMessage::StatusCurrent(StatusCurrentResponse::StateChange(state_change)) => {
+            let updated = parse_time(&state_change.time);
+            let fields = state_change.product_state.changed_fields();
+
+            match snapshot.product_state.as_mut() {
+                Some(product_state) => state_change.product_state.apply_to(product_state),
+                None => snapshot.pending_changes.push((state_change.product_state.clone(), updated))
+            }
+            stamp(snapshot, &fields, updated);
+
+            Some(DeviceStateUpdate::ProductState { serial: String::from(serial), fields })
+        },
+        DeviceMessage::PurifierStatusCurrent(PurifierStatusCurrentResponse::CurrentState(current_state)) => {
+            let updated = parse_time(&current_state.time);
+            let mut purifier_state = current_state.product_state.clone();
+            for (change, changed) in snapshot.pending_purifier_changes.drain(..) {
+                if changed > updated {
+                    change.apply_to(&mut purifier_state);
+                }
+            }
+
+            let fields = match &snapshot.purifier_state {
+                Some(old) => PurifierProductStateChange::between(old, &purifier_state).changed_fields(),
+                None => PurifierProductState::FIELDS.to_vec()
+            };
+
+            snapshot.purifier_state = Some(purifier_state);
+            stamp(snapshot, &fields, updated);
+
+            Some(DeviceStateUpdate::ProductState { serial: String::from(serial), fields })
+        },
+        DeviceMessage::PurifierStatusCurrent(PurifierStatusCurrentResponse::StateChange(state_change)) => {
+            let updated = parse_time(&state_change.time);
+            let fields = state_change.product_state.changed_fields();
+
+            match snapshot.purifier_state.as_mut() {
+                Some(purifier_state) => state_change.product_state.apply_to(purifier_state),
+                None => snapshot.pending_purifier_changes.push((state_change.product_state.clone(), updated))
+            }
+            stamp(snapshot, &fields, updated);
+
+            Some(DeviceStateUpdate::ProductState { serial: String::from(serial), fields })
+        },
+        DeviceMessage::StatusCurrent(StatusCurrentResponse::EnvironmentalCurrentSensorData(sensor_data)) => {
+            snapshot.environment = Some(EnvironmentCurrentSensorData::from_raw(&sensor_data.data));
+            snapshot.sensor_reading = Some(SensorReading::from(&sensor_data.data));
+            snapshot.environment_updated = Some(parse_time(&sensor_data.time));
+
+            Some(DeviceStateUpdate::Environment { serial: String::from(serial) })
+        },
+        DeviceMessage::PurifierStatusCurrent(PurifierStatusCurrentResponse::EnvironmentalCurrentSensorData(sensor_data)) => {
+            let environment = PurifierEnvironmentCurrentSensorData::from_raw(&sensor_data.data);
+
+            snapshot.sensor_reading = Some(SensorReading::from(&environment));
+            snapshot.purifier_environment = Some(environment);
+            snapshot.environment_updated = Some(parse_time(&sensor_data.time));
+
+            Some(DeviceStateUpdate::Environment { serial: String::from(serial) })
+        },
+        DeviceMessage::StatusConnection(connection) => {
+            snapshot.connection = Some(connection.clone());
+            snapshot.connection_updated = Some(parse_time(connection.time()));
+
+            Some(DeviceStateUpdate::Connection { serial: String::from(serial) })
+        },
+        DeviceMessage::Unrecognized { .. } => None
+    }
+}
+
+/// Keeps a newer time already recorded, e.g. by a replayed STATE-CHANGE.
+fn stamp(snapshot: &mut DeviceSnapshot, fields: &[ProductStateField], updated: DateTime<Utc>) {
+    for field in fields.iter() {
+        let last_updated = snapshot.product_state_updated.entry(*field).or_insert(updated);
+        if *last_updated < updated {
+            *last_updated = updated;
+        }
+    }
+}
+
+fn parse_time(time: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(time)
+        .map(|time| time.with_timezone(&Utc))
+        .unwrap_or_else(|_| Utc::now())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::control::*;
+    use crate::units::Temperature;
+
+    const SERIAL: &str = "ABC-DE-FGH1234A";
+
+    fn parse(topic: &str, payload: &str) -> DeviceMessage {
+        DeviceMessage::from_mqtt(&format!("455/{}/{}", SERIAL, topic), payload.as_bytes())
+    }
+
+    fn current_state() -> DeviceMessage {
+        current_state_at("2020-05-19T14:53:04.000Z")
+    }
+
+    fn current_state_at(time: &str) -> DeviceMessage {
+        parse("status/current", &r#"
+            {
+                "msg": "CURRENT-STATE",
+                "time": "TIME",
+                "mode-reason": "LSCH",
+                "state-reason": "ENV",
+                "dial": "OFF",
+                "rssi": "-35",
+                "product-state": {
+                    "fmod": "AUTO",
+                    "fnst": "FAN",
+                    "fnsp": "AUTO",
+                    "qtar": "0001",
+                    "oson": "ON",
+                    "rhtm": "ON",
+                    "filf": "3732",
+                    "ercd": "NONE",
+                    "nmod": "ON",
+                    "wacd": "NONE",
+                    "hmod": "OFF",
+                    "hmax": "2982",
+                    "hsta": "OFF",
+                    "ffoc": "OFF",
+                    "tilt": "OK"
+                },
+                "scheduler": {
+                    "srsc": "e854",
+                    "dstv": "0000",
+                    "tzid": "0001"
+                }
+            }"#.replace("TIME", time))
+    }
+
+    fn state_change() -> DeviceMessage {
+        parse("status/current", r#"
+            {
+                "msg": "STATE-CHANGE",
+                "time": "2020-05-19T15:00:00.000Z",
+                "mode-reason": "RAPP",
+                "state-reason": "MODE",
+                "product-state": {
+                    "fmod": ["AUTO", "FAN"],
+                    "fnsp": ["AUTO", "0006"],
+                    "oson": ["ON", "ON"]
+                }
+            }"#)
+    }
+
+    #[test]
+    fn it_applies_state_change_on_top_of_current_state() {
+        let store = DeviceStateStore::new();
+
+        store.apply(SERIAL, &current_state());
+        let actual = store.apply(SERIAL, &state_change());
+
+        let expected = DeviceStateUpdate::ProductState {
+            serial: String::from(SERIAL),
+            fields: vec![ProductStateField::FanMode, ProductStateField::FanSpeed]
+        };
+        assert_eq!(actual, Some(expected));
+
+        let snapshot = store.snapshot(SERIAL).unwrap();
+        let product_state = snapshot.product_state.unwrap();
+        assert_eq!(product_state.fan_mode, FanMode::Fan);
+        assert_eq!(product_state.fan_speed, FanSpeed::Speed_6);
+        assert_eq!(product_state.night_mode, NightMode::On);
+
+        let initial = parse_time("2020-05-19T14:53:04.000Z");
+        let changed = parse_time("2020-05-19T15:00:00.000Z");
+        assert_eq!(snapshot.product_state_updated[&ProductStateField::FanSpeed], changed);
+        assert_eq!(snapshot.product_state_updated[&ProductStateField::OscillationStatus], initial);
+    }
+
+    #[test]
+    fn it_replays_state_change_received_before_current_state() {
+        let store = DeviceStateStore::new();
+
+        let expected = DeviceStateUpdate::ProductState {
+            serial: String::from(SERIAL),
+            fields: vec![ProductStateField::FanMode, ProductStateField::FanSpeed]
+        };
+        assert_eq!(store.apply(SERIAL, &state_change()), Some(expected));
+        assert_eq!(store.product_state(SERIAL), None);
+
+        store.apply(SERIAL, &current_state());
+
+        let snapshot = store.snapshot(SERIAL).unwrap();
+        let product_state = snapshot.product_state.unwrap();
+        assert_eq!(product_state.fan_mode, FanMode::Fan);
+        assert_eq!(product_state.fan_speed, FanSpeed::Speed_6);
+        assert!(snapshot.pending_changes.is_empty());
+        assert_eq!(snapshot.product_state_updated[&ProductStateField::FanSpeed], parse_time("2020-05-19T15:00:00.000Z"));
+        assert_eq!(snapshot.product_state_updated[&ProductStateField::NightMode], parse_time("2020-05-19T14:53:04.000Z"));
+
+        let store = DeviceStateStore::new();
+        store.apply(SERIAL, &state_change());
+        store.apply(SERIAL, &current_state_at("2020-05-19T15:10:00.000Z"));
+        assert_eq!(store.product_state(SERIAL).unwrap().fan_mode, FanMode::Auto);
+    }
+
+    #[test]
+    fn it_tracks_purifier_generation_state() {
+        let store = DeviceStateStore::new();
+        let topic = format!("527/{}/status/current", SERIAL);
+
+        store.apply(SERIAL, &DeviceMessage::from_mqtt(&topic, br#"
+            {
+                "msg": "CURRENT-STATE",
+                "time": "2021-03-08T10:00:00.000Z",
+                "mode-reason": "LAPP",
+                "state-reason": "MODE",
+                "rssi": "-46",
+                "product-state": {
+                    "fpwr": "ON", "fdir": "ON", "auto": "OFF", "oscs": "ON", "oson": "ON",
+                    "nmod": "OFF", "rhtm": "ON", "fnst": "FAN", "ercd": "NONE", "wacd": "NONE",
+                    "fnsp": "0004", "bril": "0002", "cflr": "INV", "hflr": "0095", "sltm": "OFF",
+                    "osal": "0045", "osau": "0135", "ancp": "CUST",
+                    "hmod": "OFF", "hmax": "2980", "hsta": "OFF", "tilt": "OK"
+                }
+            }"#));
+        let actual = store.apply(SERIAL, &DeviceMessage::from_mqtt(&topic, br#"
+            {
+                "msg": "STATE-CHANGE",
+                "time": "2021-03-08T10:05:00.000Z",
+                "mode-reason": "LAPP",
+                "state-reason": "MODE",
+                "product-state": {
+                    "auto": ["OFF", "ON"],
+                    "fnsp": ["0004", "AUTO"],
+                    "osau": ["0135", "0135"]
+                }
+            }"#));
+
+        let expected = DeviceStateUpdate::ProductState {
+            serial: String::from(SERIAL),
+            fields: vec![ProductStateField::FanSpeed, ProductStateField::AutoMode]
+        };
+        assert_eq!(actual, Some(expected));
+
+        let device_state = store.device_state(SERIAL).unwrap();
+        assert!(device_state.power);
+        assert!(device_state.auto_mode);
+        assert_eq!(device_state.fan_speed, FanSpeed::Auto);
+        assert_eq!(device_state.oscillation_angles, Some((45, 135)));
+        assert_eq!(device_state.hepa_filter_life_percent, Some(95));
+        assert_eq!(device_state.carbon_filter_life_percent, None);
+        assert_eq!(device_state.heat_target, Some(Temperature::from_kelvin(298.0)));
+    }
+
+    #[test]
+    fn it_tracks_humidifier_state_and_warnings() {
+        let store = DeviceStateStore::new();
+        let topic = format!("358/{}/status/current", SERIAL);
+
+        store.apply(SERIAL, &DeviceMessage::from_mqtt(&topic, br#"
+            {
+                "msg": "CURRENT-STATE",
+                "time": "2021-03-08T10:00:00.000Z",
+                "mode-reason": "LAPP",
+                "state-reason": "MODE",
+                "rssi": "-46",
+                "product-state": {
+                    "fpwr": "ON", "auto": "OFF", "oscs": "OFF", "oson": "OFF", "nmod": "OFF",
+                    "rhtm": "ON", "fnst": "FAN", "ercd": "NONE", "wacd": "TNKE", "fnsp": "0004",
+                    "hume": "HUMD", "haut": "OFF", "humt": "0050", "rect": "0045", "wath": "0675",
+                    "clcr": "CLNO", "cltr": "0000", "cdrr": "0060", "sltm": "0030"
+                }
+            }"#));
+        let actual = store.apply(SERIAL, &DeviceMessage::from_mqtt(&topic, br#"
+            {
+                "msg": "STATE-CHANGE",
+                "time": "2021-03-08T10:05:00.000Z",
+                "mode-reason": "LAPP",
+                "state-reason": "MODE",
+                "product-state": {
+                    "humt": ["0050", "0060"],
+                    "clcr": ["CLNO", "CLAC"]
+                }
+            }"#));
+
+        let expected = DeviceStateUpdate::ProductState {
+            serial: String::from(SERIAL),
+            fields: vec![ProductStateField::HumidityTarget, ProductStateField::CleanCycle]
+        };
+        assert_eq!(actual, Some(expected));
+
+        let device_state = store.device_state(SERIAL).unwrap();
+        assert_eq!(device_state.humidification, Some(true));
+        assert_eq!(device_state.humidity_target_percent, Some(60));
+        assert_eq!(device_state.water_hardness, Some(WaterHardness::Hard));
+        assert_eq!(device_state.clean_cycle, Some(CleanCycle::Active));
+        assert_eq!(device_state.clean_time_remaining_minutes, Some(60));
+        assert_eq!(device_state.warnings, vec![DeviceWarning::EmptyWaterTank]);
+        assert_eq!(device_state.sleep_timer, Some(std::time::Duration::from_secs(30 * 60)));
+    }
+
+    #[test]
+    fn it_reports_only_fields_changed_by_current_state() {
+        let store = DeviceStateStore::new();
+
+        store.apply(SERIAL, &current_state());
+        let actual = store.apply(SERIAL, &current_state_at("2020-05-19T15:10:00.000Z"));
+
+        let expected = DeviceStateUpdate::ProductState {
+            serial: String::from(SERIAL),
+            fields: vec![]
+        };
+        assert_eq!(actual, Some(expected));
+        assert_eq!(store.snapshot(SERIAL).unwrap().product_state_updated[&ProductStateField::FanMode],
+                   parse_time("2020-05-19T14:53:04.000Z"));
+    }
+
+    #[tokio::test]
+    async fn it_notifies_subscribers() {
+        let store = DeviceStateStore::new();
+        let mut receiver = store.subscribe();
+
+        store.apply(SERIAL, &parse("status/current", r#"
+            {
+                "msg":"ENVIRONMENTAL-CURRENT-SENSOR-DATA",
+                "time":"2020-06-09T14:05:04.000Z",
+                "data": {
+                    "tact":"2980",
+                    "hact":"0056",
+                    "pact":"0003",
+                    "vact":"0002",
+                    "sltm":"OFF"
+                }
+            }"#));
+
+        let actual = receiver.recv().await.unwrap();
+
+        assert_eq!(actual, DeviceStateUpdate::Environment { serial: String::from(SERIAL) });
+        assert_eq!(store.environment(SERIAL).unwrap().humidity_percentage, Some(56.0));
+        assert_eq!(store.sensor_reading(SERIAL).unwrap().sleep_timer, None);
+    }
+
+    #[test]
+    fn it_tracks_device_online_status() {
+        let store = DeviceStateStore::new();
+
+        store.apply(SERIAL, &parse("status/connection", r#"
+            {
+                "msg": "GOODBYE",
+                "time": "2020-06-09T14:05:04.000Z",
+                "reason": "UNKNOWN"
+            }"#));
+
+        let snapshot = store.snapshot(SERIAL).unwrap();
+        assert_eq!(snapshot.is_online(), Some(false));
+        assert_eq!(snapshot.connection_updated, Some(parse_time("2020-06-09T14:05:04.000Z")));
+    }
+}
@@ -0,0 +1,159 @@
+use std::time::{Duration, Instant};
+
+use super::api_error::*;
+use super::command::*;
+use super::control::*;
+use super::units::*;
+
+/// Host side control loop for Hot+Cool devices.
+///
+/// Heating starts once the temperature drops `hysteresis_kelvin` below the target and stops
+/// once it rises the same amount above it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ThermostatSettings {
+    pub target: Temperature,
+    pub hysteresis_kelvin: f32,
+    /// Heating is stopped after running this long, and stays off until `Thermostat::resume`.
+    pub max_run_time: Option<Duration>
+}
+
+impl Default for ThermostatSettings {
+    fn default() -> Self {
+        ThermostatSettings {
+            target: Temperature::from_celsius(21.0),
+            hysteresis_kelvin: 0.5,
+            max_run_time: None
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum HeatingStopReason {
+    TargetReached,
+    MaxRunTime,
+    Tilted
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ThermostatAction {
+    StartHeating,
+    StopHeating(HeatingStopReason)
+}
+
+impl ThermostatAction {
+    pub fn command(&self, settings: &ThermostatSettings) -> StateSetCommand {
+        match self {
+            ThermostatAction::StartHeating => StateSetCommand::new()
+                .heat_mode(HeatMode::On)
+                .heat_target(settings.target),
+            ThermostatAction::StopHeating(_) => StateSetCommand::new().heat_mode(HeatMode::Off)
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Thermostat {
+    settings: ThermostatSettings,
+    heating_since: Option<Instant>,
+    locked_out: bool
+}
+
+impl Thermostat {
+    pub fn new(settings: ThermostatSettings) -> Result<Self, CommandError> {
+        StateSetCommand::new().heat_target(settings.target).validate()?;
+
+        if settings.hysteresis_kelvin < 0.0 || settings.hysteresis_kelvin.is_nan() {
+            return Err(CommandError::HysteresisOutOfRange(settings.hysteresis_kelvin));
+        }
+
+        Ok(Thermostat {
+            settings,
+            heating_since: None,
+            locked_out: false
+        })
+    }
+
+    pub fn settings(&self) -> &ThermostatSettings {
+        &self.settings
+    }
+
+    pub fn is_heating(&self) -> bool {
+        self.heating_since.is_some()
+    }
+
+    /// Whether heating was stopped by `max_run_time` and waits for `resume`.
+    pub fn is_locked_out(&self) -> bool {
+        self.locked_out
+    }
+
+    pub fn resume(&mut self) {
+        self.locked_out = false;
+    }
+
+    /// Feeds a temperature reading; returns the action to send when heating has to change.
+    pub fn update(&mut self, now: Instant, temperature: Temperature, tilt: &TiltState) -> Option<ThermostatAction> {
+        let tilted = *tilt == TiltState::Yes;
+        let (temperature, target) = (temperature.kelvin(), self.settings.target.kelvin());
+        let run_time_exceeded = match (self.heating_since, self.settings.max_run_time) {
+            (Some(since), Some(max_run_time)) => now.duration_since(since) >= max_run_time,
+            _ => false
+        };
+
+        let action = match self.heating_since {
+            Some(_) if tilted =>
+                Some(ThermostatAction::StopHeating(HeatingStopReason::Tilted)),
+            Some(_) if run_time_exceeded => {
+                self.locked_out = true;
+                Some(ThermostatAction::StopHeating(HeatingStopReason::MaxRunTime))
+            },
+            Some(_) if temperature >= target + self.settings.hysteresis_kelvin =>
+                Some(ThermostatAction::StopHeating(HeatingStopReason::TargetReached)),
+            None if !tilted && !self.locked_out && temperature <= target - self.settings.hysteresis_kelvin =>
+                Some(ThermostatAction::StartHeating),
+            _ => None
+        };
+
+        match action {
+            Some(ThermostatAction::StartHeating) => self.heating_since = Some(now),
+            Some(ThermostatAction::StopHeating(_)) => self.heating_since = None,
+            None => {}
+        }
+
+        action
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_heats_with_hysteresis_and_safety_cut_offs() {
+        let start = Instant::now();
+        let mut thermostat = Thermostat::new(ThermostatSettings {
+            target: Temperature::from_kelvin(294.15),
+            hysteresis_kelvin: 0.5,
+            max_run_time: Some(Duration::from_secs(3600))
+        }).unwrap();
+
+        assert_eq!(thermostat.update(start, Temperature::from_kelvin(294.0), &TiltState::No), None);
+        assert_eq!(thermostat.update(start, Temperature::from_kelvin(293.5), &TiltState::No), Some(ThermostatAction::StartHeating));
+        assert_eq!(thermostat.update(start, Temperature::from_kelvin(294.5), &TiltState::No), None);
+        assert_eq!(thermostat.update(start, Temperature::from_kelvin(294.7), &TiltState::No), Some(ThermostatAction::StopHeating(HeatingStopReason::TargetReached)));
+
+        thermostat.update(start, Temperature::from_kelvin(293.0), &TiltState::No);
+        assert_eq!(thermostat.update(start, Temperature::from_kelvin(293.0), &TiltState::Yes), Some(ThermostatAction::StopHeating(HeatingStopReason::Tilted)));
+        assert_eq!(thermostat.update(start, Temperature::from_kelvin(293.0), &TiltState::Yes), None);
+
+        thermostat.update(start, Temperature::from_kelvin(293.0), &TiltState::No);
+        let later = start + Duration::from_secs(3600);
+        assert_eq!(thermostat.update(later, Temperature::from_kelvin(293.0), &TiltState::No), Some(ThermostatAction::StopHeating(HeatingStopReason::MaxRunTime)));
+        assert_eq!(thermostat.update(later, Temperature::from_kelvin(293.0), &TiltState::No), None);
+        thermostat.resume();
+        assert_eq!(thermostat.update(later, Temperature::from_kelvin(293.0), &TiltState::No), Some(ThermostatAction::StartHeating));
+
+        assert!(Thermostat::new(ThermostatSettings { target: Temperature::from_kelvin(320.0), ..Default::default() }).is_err());
+        assert_eq!(Thermostat::new(ThermostatSettings { hysteresis_kelvin: -0.5, ..Default::default() }).unwrap_err(),
+                   CommandError::HysteresisOutOfRange(-0.5));
+    }
+}
@@ -0,0 +1,234 @@
+use std::str::FromStr;
+
+use num_traits::{Float, FromPrimitive};
+use serde::{Deserialize, Serialize};
+
+use super::api_error::*;
+
+const KELVIN_OFFSET: f64 = 273.15;
+
+fn constant<T: FromPrimitive>(value: f64) -> T {
+    T::from_f64(value).unwrap()
+}
+
+pub fn kelvin_to_celsius<T: Float + FromPrimitive>(kelvin: T) -> T {
+    kelvin - constant(KELVIN_OFFSET)
+}
+
+pub fn celsius_to_kelvin<T: Float + FromPrimitive>(celsius: T) -> T {
+    celsius + constant(KELVIN_OFFSET)
+}
+
+pub fn celsius_to_fahrenheit<T: Float + FromPrimitive>(celsius: T) -> T {
+    celsius * constant(9.0 / 5.0) + constant(32.0)
+}
+
+pub fn fahrenheit_to_celsius<T: Float + FromPrimitive>(fahrenheit: T) -> T {
+    (fahrenheit - constant(32.0)) * constant(5.0 / 9.0)
+}
+
+pub fn kelvin_to_fahrenheit<T: Float + FromPrimitive>(kelvin: T) -> T {
+    celsius_to_fahrenheit(kelvin_to_celsius(kelvin))
+}
+
+pub fn fahrenheit_to_kelvin<T: Float + FromPrimitive>(fahrenheit: T) -> T {
+    celsius_to_kelvin(fahrenheit_to_celsius(fahrenheit))
+}
+
+/// Devices report and accept temperatures (`tact`, `hmax`) in tenths of a Kelvin.
+pub fn from_deci_kelvin<T: Float + FromPrimitive>(deci_kelvin: T) -> T {
+    deci_kelvin / constant(10.0)
+}
+
+pub fn to_deci_kelvin(kelvin: f32) -> i32 {
+    (kelvin * 10.0).round() as i32
+}
+
+/// Reads a wire value like "2980"; markers such as `OFF` or `INIT` give `None`.
+pub fn parse_deci_kelvin(value: &str) -> Option<f32> {
+    value.parse::<f32>().ok().map(from_deci_kelvin)
+}
+
+/// Encodes a temperature the way devices expect it, e.g. "2980" for 298 K.
+pub fn format_deci_kelvin(kelvin: f32) -> String {
+    format!("{:04}", to_deci_kelvin(kelvin))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TemperatureUnit {
+    Kelvin,
+    Celsius,
+    Fahrenheit
+}
+
+impl TemperatureUnit {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            TemperatureUnit::Kelvin => "K",
+            TemperatureUnit::Celsius => "°C",
+            TemperatureUnit::Fahrenheit => "°F"
+        }
+    }
+}
+
+/// A temperature that knows its scale, stored in Kelvin.
+///
+/// Formats in Kelvin by default, honouring the precision (`{:.1}`); use `in_unit` for
+/// other scales. Parses values with a unit suffix like "21.5 °C", "70F" or "294.15 K",
+/// rejecting anything that isn't finite or lies below absolute zero.
+/// Serializes as a bare Kelvin number.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Temperature(f32);
+
+impl Temperature {
+    pub fn from_kelvin(kelvin: f32) -> Self {
+        Temperature(kelvin)
+    }
+
+    pub fn from_celsius(celsius: f32) -> Self {
+        Temperature(celsius_to_kelvin(celsius))
+    }
+
+    pub fn from_fahrenheit(fahrenheit: f32) -> Self {
+        Temperature(fahrenheit_to_kelvin(fahrenheit))
+    }
+
+    pub fn from_deci_kelvin(deci_kelvin: i32) -> Self {
+        Temperature(from_deci_kelvin(deci_kelvin as f32))
+    }
+
+    pub fn new(value: f32, unit: TemperatureUnit) -> Self {
+        match unit {
+            TemperatureUnit::Kelvin => Self::from_kelvin(value),
+            TemperatureUnit::Celsius => Self::from_celsius(value),
+            TemperatureUnit::Fahrenheit => Self::from_fahrenheit(value)
+        }
+    }
+
+    pub fn kelvin(self) -> f32 {
+        self.0
+    }
+
+    pub fn celsius(self) -> f32 {
+        kelvin_to_celsius(self.0)
+    }
+
+    pub fn fahrenheit(self) -> f32 {
+        kelvin_to_fahrenheit(self.0)
+    }
+
+    pub fn deci_kelvin(self) -> i32 {
+        to_deci_kelvin(self.0)
+    }
+
+    pub fn value(self, unit: TemperatureUnit) -> f32 {
+        match unit {
+            TemperatureUnit::Kelvin => self.kelvin(),
+            TemperatureUnit::Celsius => self.celsius(),
+            TemperatureUnit::Fahrenheit => self.fahrenheit()
+        }
+    }
+
+    pub fn in_unit(self, unit: TemperatureUnit) -> TemperatureDisplay {
+        TemperatureDisplay { temperature: self, unit }
+    }
+}
+
+impl std::fmt::Display for Temperature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.in_unit(TemperatureUnit::Kelvin).fmt(f)
+    }
+}
+
+impl FromStr for Temperature {
+    type Err = DysonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let suffixes = [
+            ("°C", TemperatureUnit::Celsius),
+            ("°F", TemperatureUnit::Fahrenheit),
+            ("C", TemperatureUnit::Celsius),
+            ("F", TemperatureUnit::Fahrenheit),
+            ("K", TemperatureUnit::Kelvin),
+        ];
+
+        suffixes.iter()
+            .find_map(|(suffix, unit)| {
+                let value = s.strip_suffix(suffix).or_else(|| s.strip_suffix(&suffix.to_lowercase()))?;
+                value.trim().parse::<f32>().ok().map(|value| Temperature::new(value, *unit))
+            })
+            .filter(|temperature| temperature.kelvin().is_finite() && temperature.kelvin() >= 0.0)
+            .ok_or_else(|| DysonError::InvalidTemperature(String::from(s)))
+    }
+}
+
+/// Formats a `Temperature` in the given unit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TemperatureDisplay {
+    temperature: Temperature,
+    unit: TemperatureUnit
+}
+
+impl std::fmt::Display for TemperatureDisplay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = self.temperature.value(self.unit);
+
+        match f.precision() {
+            Some(precision) => write!(f, "{:.*} {}", precision, value, self.unit.symbol()),
+            None => write!(f, "{} {}", value, self.unit.symbol())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const EPSILON: f64 = 1e-6;
+
+    #[test]
+    fn it_converts_between_scales() {
+        let samples = vec![
+            (173.15, -100.0, -148.0),
+            (273.15, 0.0, 32.0),
+            (373.15, 100.0, 212.0),
+        ];
+
+        for (kelvin, celsius, fahrenheit) in samples {
+            assert!((kelvin_to_celsius::<f64>(kelvin) - celsius).abs() < EPSILON);
+            assert!((celsius_to_kelvin::<f64>(celsius) - kelvin).abs() < EPSILON);
+            assert!((kelvin_to_fahrenheit::<f64>(kelvin) - fahrenheit).abs() < EPSILON);
+            assert!((fahrenheit_to_kelvin::<f64>(fahrenheit) - kelvin).abs() < EPSILON);
+            assert!((celsius_to_fahrenheit::<f64>(celsius) - fahrenheit).abs() < EPSILON);
+            assert!((fahrenheit_to_celsius::<f64>(fahrenheit) - celsius).abs() < EPSILON);
+        }
+    }
+
+    #[test]
+    fn it_encodes_deci_kelvin() {
+        assert_eq!(parse_deci_kelvin("2980"), Some(298.0));
+        assert_eq!(parse_deci_kelvin("OFF"), None);
+        assert_eq!(format_deci_kelvin(298.0), "2980");
+        assert_eq!(Temperature::from_deci_kelvin(2980).deci_kelvin(), 2980);
+    }
+
+    #[test]
+    fn it_formats_and_parses_temperatures() {
+        let temperature = Temperature::from_celsius(21.5);
+
+        assert_eq!(format!("{:.1}", temperature.in_unit(TemperatureUnit::Celsius)), "21.5 °C");
+        assert_eq!(format!("{:.2}", temperature), "294.65 K");
+        assert_eq!(format!("{:.1}", temperature.in_unit(TemperatureUnit::Fahrenheit)), "70.7 °F");
+
+        for input in ["21.5 °C", "21.5C", "70.7 °F", "294.65 K", "294.65k"].iter() {
+            let actual: Temperature = input.parse().unwrap();
+            assert!((actual.kelvin() - temperature.kelvin()).abs() < 0.1, "{}", input);
+        }
+
+        for input in ["21.5", "NaN K", "inf C", "-300 °C", "-1 K"].iter() {
+            assert!(matches!(input.parse::<Temperature>(), Err(DysonError::InvalidTemperature(_))), "{}", input);
+        }
+    }
+}
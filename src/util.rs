@@ -1,4 +1,5 @@
 use crypto::buffer::{ ReadBuffer, WriteBuffer, BufferResult };
+use crypto::digest::Digest;
 
 pub fn decrypt(encrypted_data: &[u8], key: &[u8], iv: &[u8]) -> Result<Vec<u8>, crypto::symmetriccipher::SymmetricCipherError> {
     let mut decryptor = crypto::aes::cbc_decryptor(
@@ -49,3 +50,27 @@ pub fn encrypt(data: &[u8], key: &[u8], iv: &[u8]) -> Result<Vec<u8>, crypto::sy
 
     Ok(final_result)
 }
+
+pub fn sha512_base64(data: &str) -> String {
+    let mut hasher = crypto::sha2::Sha512::new();
+    hasher.input_str(data);
+
+    let mut hash = [0; 64];
+    hasher.result(&mut hash);
+
+    base64::encode(&hash[..])
+}
+
+pub fn current_timestamp() -> String {
+    chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn it_hashes_to_base64_sha512() {
+        let actual = super::sha512_base64("abc");
+
+        assert_eq!(actual, "3a81oZNherrMQXNJriBBMRLm+k6JqX6iCp7u5ktV05ohkpkqJ0/BqDa6PCOj/uu9RU1EI2Q86A4qmslPpUyknw==");
+    }
+}